@@ -41,12 +41,10 @@ fn main() -> DuckResult<()> {
     let args = Args {
         input: PathBuf::from("duckdb_test_data"),
         output: Some(PathBuf::from("duckdb_test_out")),
-        symbol: "BTCUSDT".to_string(),
+        symbol: Some("BTCUSDT".to_string()),
         interval: "1,5".to_string(),
         format: "duckdb".to_string(),
-        benchmark: false,
-        progress: false,
-        memory_stats: false,
+        ..Default::default()
     };
     candle_batch_aggregator::formats::duckdb::process_duckdb_batch(&args).unwrap();
 
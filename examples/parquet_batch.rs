@@ -1,65 +1,69 @@
-use polars::prelude::*;
-use candle_generator::{Trade, Instrument, Pair, MarketType, Side, Candle};
-use chrono::{Utc, TimeZone};
-use std::fs;
+use arrow::array::{ArrayRef, Float64Array, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use std::fs::{self, File};
 use std::path::PathBuf;
+use std::sync::Arc;
 use candle_batch_aggregator::formats::parquet::process_parquet_batch;
 use candle_batch_aggregator::Args;
 
-fn main() -> polars::prelude::PolarsResult<()> {
+fn main() -> anyhow::Result<()> {
     // 1. Генерируем трейды
-    let trades = vec![
+    let trades = [
         (1714000000000i64, "binance", "BTC", "USDT", "Spot", "t1", 50000.0, 0.1, "Buy"),
         (1714000060000i64, "binance", "BTC", "USDT", "Spot", "t2", 50100.0, 0.2, "Sell"),
         (1714000120000i64, "binance", "BTC", "USDT", "Spot", "t3", 50200.0, 0.3, "Buy"),
     ];
-    let timestamps: Vec<_> = trades.iter().map(|t| t.0).collect();
-    let exchanges: Vec<_> = trades.iter().map(|t| t.1.to_string()).collect();
-    let base_ids: Vec<_> = trades.iter().map(|t| t.2.to_string()).collect();
-    let quote_ids: Vec<_> = trades.iter().map(|t| t.3.to_string()).collect();
-    let market_types: Vec<_> = trades.iter().map(|t| t.4.to_string()).collect();
-    let ids: Vec<_> = trades.iter().map(|t| t.5.to_string()).collect();
-    let prices: Vec<_> = trades.iter().map(|t| t.6).collect();
-    let amounts: Vec<_> = trades.iter().map(|t| t.7).collect();
-    let sides: Vec<_> = trades.iter().map(|t| t.8.to_string()).collect();
-    let df = DataFrame::new(vec![
-        Series::new("timestamp", timestamps),
-        Series::new("exchange", exchanges),
-        Series::new("base_id", base_ids),
-        Series::new("quote_id", quote_ids),
-        Series::new("market_type", market_types),
-        Series::new("id", ids),
-        Series::new("price", prices),
-        Series::new("amount", amounts),
-        Series::new("side", sides),
+    let text = |values: Vec<&str>| -> ArrayRef { Arc::new(StringArray::from(values)) };
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("timestamp", DataType::Int64, false),
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("base_id", DataType::Utf8, false),
+        Field::new("quote_id", DataType::Utf8, false),
+        Field::new("market_type", DataType::Utf8, false),
+        Field::new("id", DataType::Utf8, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("amount", DataType::Float64, false),
+        Field::new("side", DataType::Utf8, false),
+    ]));
+    let batch = RecordBatch::try_new(schema.clone(), vec![
+        Arc::new(Int64Array::from(trades.iter().map(|t| t.0).collect::<Vec<_>>())),
+        text(trades.iter().map(|t| t.1).collect()),
+        text(trades.iter().map(|t| t.2).collect()),
+        text(trades.iter().map(|t| t.3).collect()),
+        text(trades.iter().map(|t| t.4).collect()),
+        text(trades.iter().map(|t| t.5).collect()),
+        Arc::new(Float64Array::from(trades.iter().map(|t| t.6).collect::<Vec<_>>())),
+        Arc::new(Float64Array::from(trades.iter().map(|t| t.7).collect::<Vec<_>>())),
+        text(trades.iter().map(|t| t.8).collect()),
     ])?;
     let input_dir = PathBuf::from("parquet_test_data/BTCUSDT");
-    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&input_dir)?;
     let parquet_path = input_dir.join("trades.parquet");
-    df.write_parquet(&parquet_path, ParquetWriteOptions::default())?;
+    let mut writer = ArrowWriter::try_new(File::create(&parquet_path)?, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
     println!("Тестовые трейды сохранены в {:?}", parquet_path);
 
     // 2. Запускаем batch-агрегацию
     let args = Args {
         input: PathBuf::from("parquet_test_data"),
         output: Some(PathBuf::from("parquet_test_out")),
-        symbol: "BTCUSDT".to_string(),
+        symbol: Some("BTCUSDT".to_string()),
         interval: "1,5".to_string(),
         format: "parquet".to_string(),
-        benchmark: false,
-        progress: false,
-        memory_stats: false,
+        ..Default::default()
     };
-    candle_batch_aggregator::formats::parquet::process_parquet_batch(&args).unwrap();
+    process_parquet_batch(&args)?;
 
     // 3. Читаем результат
-    let out_path = PathBuf::from("parquet_test_out/BTCUSDT_m1/trades_m1.parquet");
+    let out_path = PathBuf::from("parquet_test_out/BTCUSDT_m1/trades_m1.csv");
     if out_path.exists() {
-        let out_df = LazyFrame::scan_parquet(&out_path.to_string_lossy(), Default::default())?.collect()?;
-        println!("\nАгрегированные свечи (m1):");
-        println!("{}", out_df);
+        let content = fs::read_to_string(&out_path)?;
+        println!("\nАгрегированные свечи (m1):\n{}", content);
     } else {
         println!("Файл {:?} не найден", out_path);
     }
     Ok(())
-} 
\ No newline at end of file
+}
@@ -1,39 +1,35 @@
-use std::fs;
 use std::path::PathBuf;
 use candle_batch_aggregator::formats::questdb::process_questdb_batch;
 use candle_batch_aggregator::Args;
 
 fn main() {
-    // 1. Подготовка: в questdb_test_data/BTCUSDT/questdb_url.txt должен быть URL на QuestDB HTTP API (CSV)
-    // Например: http://localhost:9000/exec?query=SELECT+*+FROM+trades+ORDER+BY+timestamp&fmt=csv
-    let input_dir = PathBuf::from("questdb_test_data/BTCUSDT");
-    fs::create_dir_all(&input_dir).unwrap();
-    let url_path = input_dir.join("questdb_url.txt");
-    if !url_path.exists() {
-        fs::write(&url_path, "http://localhost:9000/exec?query=SELECT+*+FROM+trades+ORDER+BY+timestamp&fmt=csv").unwrap();
-        println!("Создан файл {:?} — укажите в нём URL QuestDB", url_path);
-        return;
-    }
-
-    // 2. Запускаем batch-агрегацию
+    // 1. Подготовка: QuestDB на localhost:9000 с таблицей trades
+    // (symbol SYMBOL, price DOUBLE, amount DOUBLE, side SYMBOL, timestamp TIMESTAMP) timestamp(timestamp)
     let args = Args {
-        input: PathBuf::from("questdb_test_data"),
         output: Some(PathBuf::from("questdb_test_out")),
-        symbol: "BTCUSDT".to_string(),
+        symbol: Some("BTCUSDT".to_string()),
         interval: "1,5".to_string(),
         format: "questdb".to_string(),
-        benchmark: false,
-        progress: false,
-        memory_stats: false,
+        questdb_url: "http://localhost:9000".to_string(),
+        from: Some("2024-04-25".to_string()),
+        to: Some("2024-04-26".to_string()),
+        chunk_hours: 6,
+        progress: true,
+        ..Default::default()
     };
-    candle_batch_aggregator::formats::questdb::process_questdb_batch(&args).unwrap();
+
+    // 2. Запускаем batch-агрегацию
+    if let Err(e) = process_questdb_batch(&args) {
+        println!("Ошибка QuestDB: {:#}", e);
+        return;
+    }
 
     // 3. Читаем результат
-    let out_path = PathBuf::from("questdb_test_out/BTCUSDT_m1/questdb_m1.csv");
+    let out_path = PathBuf::from("questdb_test_out/BTCUSDT_m1/trades_m1.csv");
     if out_path.exists() {
         let content = std::fs::read_to_string(&out_path).unwrap();
         println!("\nАгрегированные свечи (m1):\n{}", content);
     } else {
         println!("Файл {:?} не найден", out_path);
     }
-} 
\ No newline at end of file
+}
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use candle_generator::{Candle, Timeframe, CandleGenerator, Trade};
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::Path;
 use csv::WriterBuilder;
 use serde::Serialize;
@@ -11,6 +12,19 @@ pub fn aggregate_trades_to_candles(/* trades, interval, ... */) -> Result<()> {
     Ok(())
 }
 
+pub fn timeframe_millis(tf: &Timeframe) -> i64 {
+    let minutes = match tf {
+        Timeframe::m5 => 5,
+        Timeframe::m15 => 15,
+        Timeframe::m30 => 30,
+        Timeframe::h1 => 60,
+        Timeframe::h4 => 240,
+        Timeframe::d1 => 1440,
+        _ => 1,
+    };
+    minutes * 60_000
+}
+
 fn aggregate_from_lower(lower: &[Candle], tf: &Timeframe) -> Vec<Candle> {
     let count = match tf {
         Timeframe::m5 => 5,
@@ -49,13 +63,58 @@ fn aggregate_from_lower(lower: &[Candle], tf: &Timeframe) -> Vec<Candle> {
     result
 }
 
+/// Данные трейда, которых нет в `Trade`, но которые дают некоторые источники (например, выгрузки Binance)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TradeExtra {
+    /// Объём в котируемой валюте (quote_qty), идёт в volume_usdt
+    pub quote_volume: Option<f64>,
+    /// Сколько сделок представляет запись (для aggTrades: last_id - first_id + 1)
+    pub trade_count: Option<u64>,
+}
+
+/// Пересчитывает trade_count и volume_usdt свечей таймфрейма `tf` по дополнительным данным трейдов.
+/// volume_usdt заменяется только если quote_volume известен у всех трейдов свечи.
+pub fn apply_trade_extras<'a>(candles: &mut [Candle], trades: impl Iterator<Item = &'a Trade>, extras: &[TradeExtra], tf: &Timeframe) {
+    let step = timeframe_millis(tf);
+    let mut buckets: HashMap<i64, (f64, u64, bool)> = HashMap::new();
+    for (trade, extra) in trades.zip(extras) {
+        let ts = trade.timestamp.timestamp_millis();
+        let bucket = buckets.entry(ts - ts.rem_euclid(step)).or_insert((0.0, 0, true));
+        match extra.quote_volume {
+            Some(quote) => bucket.0 += quote,
+            None => bucket.2 = false,
+        }
+        bucket.1 += extra.trade_count.unwrap_or(1);
+    }
+    for candle in candles {
+        if let Some(&(quote, count, complete)) = buckets.get(&candle.timestamp.timestamp_millis()) {
+            candle.trade_count = count as _;
+            if complete {
+                candle.volume_usdt = Some(quote);
+            }
+        }
+    }
+}
+
 pub fn aggregate_trades_chain<'a>(trades: impl Iterator<Item = &'a Trade> + Clone, timeframes: &[Timeframe]) -> HashMap<Timeframe, Vec<Candle>> {
+    aggregate_trades_chain_with_extras(trades, timeframes, None)
+}
+
+/// Как `aggregate_trades_chain`, но базовые свечи сначала уточняются по `extras` (по одному на трейд),
+/// чтобы trade_count и volume_usdt попали и в старшие таймфреймы
+pub fn aggregate_trades_chain_with_extras<'a>(
+    trades: impl Iterator<Item = &'a Trade> + Clone,
+    timeframes: &[Timeframe],
+    extras: Option<&[TradeExtra]>,
+) -> HashMap<Timeframe, Vec<Candle>> {
     let mut result = HashMap::new();
     if timeframes.is_empty() { return result; }
-    let generator = CandleGenerator::default();
     let base_tf = timeframes[0].clone();
-    let base_candles = generator.aggregate(trades.clone(), base_tf.clone());
-    result.insert(base_tf.clone(), base_candles.clone());
+    let mut base_candles = CandleGenerator::default().aggregate(trades.clone(), base_tf.clone());
+    if let Some(extras) = extras {
+        apply_trade_extras(&mut base_candles, trades, extras, &base_tf);
+    }
+    result.insert(base_tf, base_candles.clone());
     let mut prev = base_candles;
     for tf in timeframes.iter().skip(1) {
         let higher = aggregate_from_lower(&prev, tf);
@@ -88,6 +147,46 @@ impl From<&Candle> for SimpleCandle {
     }
 }
 
+/// Строка длинного формата: свечи нескольких таймфреймов в одном потоке
+#[derive(Debug, Serialize)]
+pub struct IntervalCandle {
+    pub interval: String,
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+impl From<&Candle> for IntervalCandle {
+    fn from(c: &Candle) -> Self {
+        Self {
+            interval: format!("{:?}", c.interval),
+            timestamp: c.timestamp.timestamp_millis(),
+            open: c.open,
+            high: c.high,
+            low: c.low,
+            close: c.close,
+            volume: c.volume,
+        }
+    }
+}
+
+/// Пишет свечи CSV в поток; `with_interval` — длинный формат с колонкой interval
+pub fn write_candles_csv_to(candles: &[Candle], writer: impl Write, with_interval: bool) -> Result<()> {
+    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(writer);
+    for candle in candles {
+        if with_interval {
+            wtr.serialize(IntervalCandle::from(candle))?;
+        } else {
+            wtr.serialize(SimpleCandle::from(candle))?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
 pub fn write_candles_csv<P: AsRef<Path>>(candles: &[Candle], out_path: P) -> Result<()> {
     let mut wtr = WriterBuilder::new().has_headers(true).from_path(out_path)?;
     for candle in candles {
@@ -146,6 +245,21 @@ mod tests {
         assert_eq!(result[&candle_generator::Timeframe::m1].len(), 1);
     }
 
+    #[test]
+    fn test_aggregate_trades_chain_with_extras() {
+        // Две агрегированные записи по 3 и 2 сделки в одной минуте
+        let trades = vec![sample_trade(1714000020000, 50000.0, 0.1), sample_trade(1714000030000, 50100.0, 0.2)];
+        let extras = vec![
+            TradeExtra { quote_volume: Some(5000.0), trade_count: Some(3) },
+            TradeExtra { quote_volume: Some(10020.0), trade_count: Some(2) },
+        ];
+        let tfs = vec![candle_generator::Timeframe::m1];
+        let result = aggregate_trades_chain_with_extras(trades.iter(), &tfs, Some(&extras));
+        let candle = &result[&candle_generator::Timeframe::m1][0];
+        assert_eq!(candle.trade_count as u64, 5);
+        assert_eq!(candle.volume_usdt, Some(15020.0));
+    }
+
     #[test]
     fn test_aggregate_trades_chain_multiple_timeframes() {
         let trades = vec![
@@ -0,0 +1,268 @@
+use super::Args;
+use anyhow::{Context, Result};
+use candle_generator::Trade;
+use chrono::{NaiveDate, TimeZone};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::pipeline::{dir_files, resolve_symbols};
+use crate::progress;
+
+/// Миллисекунд в сутках: меньшие метки времени считаются временем от начала дня
+const DAY_MS: i64 = 86_400_000;
+
+/// Значения Hive-партиций из пути (`exchange=binance/symbol=BTCUSDT/date=2024-05-01/part-0.parquet`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Partition {
+    pub exchange: Option<String>,
+    pub symbol: Option<String>,
+    pub base: Option<String>,
+    pub quote: Option<String>,
+    pub date: Option<NaiveDate>,
+}
+
+impl Partition {
+    /// Разбирает директории `key=value` пути; незнакомые ключи и имя файла не учитываются
+    pub fn from_path(path: &Path) -> Self {
+        let mut partition = Self::default();
+        let dirs = path.parent().unwrap_or(Path::new(""));
+        for component in dirs.components() {
+            let Component::Normal(segment) = component else { continue };
+            let segment = segment.to_string_lossy();
+            let Some((key, value)) = segment.split_once('=') else { continue };
+            if value.is_empty() {
+                continue;
+            }
+            let value = value.to_string();
+            match key.to_lowercase().as_str() {
+                "exchange" | "venue" => partition.exchange = Some(value),
+                "symbol" | "pair" => partition.symbol = Some(value),
+                "base" | "base_id" => partition.base = Some(value),
+                "quote" | "quote_id" => partition.quote = Some(value),
+                "date" | "dt" | "day" => {
+                    partition.date = NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                        .or_else(|_| NaiveDate::parse_from_str(&value, "%Y%m%d"))
+                        .ok()
+                }
+                _ => {}
+            }
+        }
+        partition
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Дополняет трейды значениями из пути там, где в строках их нет: пустые exchange/base/quote
+    /// (пара без base — как символ целиком, как у Tardis) и дата для времени от начала суток
+    pub fn fill(&self, trades: &mut [Trade]) {
+        let base = self.base.as_ref().or(self.symbol.as_ref());
+        let midnight = self.date.map(|d| chrono::Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).expect("midnight is valid")));
+        for trade in trades.iter_mut() {
+            let instrument = &mut trade.instrument;
+            if let (true, Some(exchange)) = (instrument.exchange.is_empty(), &self.exchange) {
+                instrument.exchange = exchange.clone();
+            }
+            if let (true, Some(base)) = (instrument.pair.base_id.is_empty(), base) {
+                instrument.pair.base_id = base.clone();
+            }
+            if let (true, Some(quote)) = (instrument.pair.quote_id.is_empty(), &self.quote) {
+                instrument.pair.quote_id = quote.clone();
+            }
+            if let Some(midnight) = midnight {
+                let ms = trade.timestamp.timestamp_millis();
+                if (0..DAY_MS).contains(&ms) {
+                    trade.timestamp = midnight + chrono::Duration::milliseconds(ms);
+                }
+            }
+        }
+    }
+}
+
+/// Stem выходного файла для источника во вложенных директориях: значения промежуточных директорий
+/// (кроме директории пары и zip-архива) идут префиксом, чтобы part-0 разных дат не перезаписывали друг друга
+pub fn nested_stem(relative: &Path, symbol: &str, stem: &str) -> String {
+    let mut parts: Vec<String> = relative
+        .parent()
+        .unwrap_or(Path::new(""))
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .filter(|segment| !segment.to_lowercase().ends_with(".zip"))
+        .map(|segment| segment.split_once('=').map(|(_, value)| value.to_string()).unwrap_or(segment))
+        .filter(|value| value != symbol)
+        .collect();
+    parts.push(stem.to_string());
+    parts.join("_")
+}
+
+/// Фильтр путей по `--include` / `--exclude`: glob'ы сопоставляются с путём относительно `--input`
+struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl PathFilter {
+    fn from_args(args: &Args) -> Result<Self> {
+        Ok(Self { include: glob_set(&args.include)?, exclude: glob_set(&args.exclude)? })
+    }
+
+    fn accepts(&self, relative: &Path) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        self.include.as_ref().is_none_or(|set| set.is_match(&path)) && !self.exclude.as_ref().is_some_and(|set| set.is_match(&path))
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("invalid glob {:?}", pattern))?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// Все файлы под `dir`, по пути; служебные файлы и директории (`.crc`, `_SUCCESS`, `_temporary`) пропускаются.
+/// Символические ссылки на директории обходятся, но каждая реальная директория — один раз (`visited`),
+/// так что ссылка на родителя не зацикливает обход
+fn walk_files(dir: &Path, files: &mut Vec<PathBuf>, visited: &mut HashSet<PathBuf>) -> Result<()> {
+    let real = fs::canonicalize(dir).with_context(|| format!("cannot resolve directory {:?}", dir))?;
+    if !visited.insert(real) {
+        progress!("  Skip: {:?} (directory already visited through a symlink)", dir);
+        return Ok(());
+    }
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("cannot read directory {:?}", dir))?
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_name().to_string_lossy().starts_with(['.', '_']))
+        .collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            walk_files(&path, files, visited)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Пара для файла при рекурсивном обходе: партиция `symbol=`, иначе директория верхнего уровня (`BTCUSDT/2024/05/x.csv`).
+/// Без них пары нет: в `exchange=binance/2024/05/x.parquet` директория 2024 — не пара
+fn path_symbol(relative: &Path) -> Option<String> {
+    Partition::from_path(relative).symbol.or_else(|| {
+        let top = relative.parent()?.components().next()?;
+        let Component::Normal(top) = top else { return None };
+        let top = top.to_string_lossy().to_string();
+        (!top.contains('=')).then_some(top)
+    })
+}
+
+/// Входные файлы по парам, в порядке обработки. `accept` отбирает файлы формата адаптера.
+/// Без `--recursive` — файлы `input/SYMBOL/*`; с ним — всё дерево, пара берётся из пути.
+pub fn discover_inputs(args: &Args, accept: impl Fn(&Path) -> bool) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    let filter = PathFilter::from_args(args)?;
+    let selected = |path: &Path| accept(path) && filter.accepts(path.strip_prefix(&args.input).unwrap_or(path));
+    if !args.recursive {
+        let mut groups = Vec::new();
+        for symbol in resolve_symbols(args)? {
+            let symbol_dir = args.input.join(&symbol);
+            if !symbol_dir.exists() { continue; }
+            let files: Vec<PathBuf> = dir_files(&symbol_dir)?.into_iter().filter(|p| selected(p)).collect();
+            groups.push((symbol, files));
+        }
+        return Ok(groups);
+    }
+    let mut files = Vec::new();
+    walk_files(&args.input, &mut files, &mut HashSet::new())?;
+    let mut by_symbol: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in files.into_iter().filter(|p| selected(p)) {
+        let relative = path.strip_prefix(&args.input).unwrap_or(&path);
+        match path_symbol(relative) {
+            Some(symbol) => by_symbol.entry(symbol).or_default().push(path),
+            None => progress!("  Skip: {:?} (no symbol directory or symbol= partition in path)", relative),
+        }
+    }
+    if args.symbol()?.eq_ignore_ascii_case("ALL") {
+        return Ok(by_symbol.into_iter().collect());
+    }
+    Ok(resolve_symbols(args)?
+        .into_iter()
+        .filter_map(|symbol| by_symbol.remove(&symbol).map(|files| (symbol, files)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_generator::{Instrument, MarketType, Pair, Side};
+
+    #[test]
+    fn test_partition_fill() {
+        let partition = Partition::from_path(Path::new("exchange=binance/symbol=BTCUSDT/date=2024-05-01/part-0.parquet"));
+        assert_eq!(partition.exchange.as_deref(), Some("binance"));
+        assert_eq!(partition.symbol.as_deref(), Some("BTCUSDT"));
+        assert_eq!(partition.date, NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert!(Partition::from_path(Path::new("BTCUSDT/a=b.csv")).is_empty());
+        let trade = |ms: i64, exchange: &str| Trade {
+            instrument: Instrument {
+                pair: Pair { base_id: String::new(), quote_id: String::new() },
+                exchange: exchange.to_string(),
+                market_type: MarketType::Spot,
+            },
+            id: "1".to_string(),
+            price: 50000.0,
+            amount: 0.1,
+            side: Side::Buy,
+            timestamp: chrono::Utc.timestamp_millis_opt(ms).unwrap(),
+        };
+        // Время от начала суток и пустая биржа берутся из пути, полные значения из строк не меняются
+        let mut trades = vec![trade(60_000, ""), trade(1714521600000, "okx")];
+        partition.fill(&mut trades);
+        assert_eq!(trades[0].timestamp.timestamp_millis(), 1714521660000);
+        assert_eq!(trades[0].instrument.exchange, "binance");
+        assert_eq!(trades[0].instrument.pair.base_id, "BTCUSDT");
+        assert_eq!(trades[1].timestamp.timestamp_millis(), 1714521600000);
+        assert_eq!(trades[1].instrument.exchange, "okx");
+        let relative = Path::new("exchange=binance/symbol=BTCUSDT/date=2024-05-01/part-0.parquet");
+        assert_eq!(nested_stem(relative, "BTCUSDT", "part-0"), "binance_2024-05-01_part-0");
+        assert_eq!(nested_stem(Path::new("BTCUSDT/2024-04.zip/trades-01.csv"), "BTCUSDT", "trades-01"), "trades-01");
+    }
+
+    #[test]
+    fn test_discover_inputs_recursive() {
+        let root = std::env::temp_dir().join(format!("candle_batch_aggregator_discovery_{}", std::process::id()));
+        let files = [
+            "exchange=binance/symbol=BTCUSDT/date=2024-05-01/part-0.parquet",
+            "exchange=binance/symbol=BTCUSDT/date=2024-05-02/part-0.parquet",
+            "exchange=binance/symbol=BTCUSDT/date=2024-05-02/_SUCCESS",
+            "exchange=binance/symbol=ETHUSDT/date=2024-05-01/part-0.parquet",
+            "ETHUSDT/2024/05/trades.parquet",
+            "exchange=okx/2024/05/trades.parquet",
+            "loose.parquet",
+        ];
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"").unwrap();
+        }
+        // Ссылка на корень внутри дерева не зацикливает обход
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("ETHUSDT/2024/loop")).unwrap();
+        let args = Args { input: root.clone(), recursive: true, exclude: vec!["**/date=2024-05-02/**".to_string()], ..Args::default() };
+        let groups = discover_inputs(&args, |_| true).unwrap();
+        let names: Vec<(String, usize)> = groups.iter().map(|(s, f)| (s.clone(), f.len())).collect();
+        assert_eq!(names, vec![("BTCUSDT".to_string(), 1), ("ETHUSDT".to_string(), 2)]);
+        let args = Args { symbol: Some("ETHUSDT".to_string()), include: vec!["ETHUSDT/**".to_string()], ..args };
+        let groups = discover_inputs(&args, |_| true).unwrap();
+        fs::remove_dir_all(&root).ok();
+        assert_eq!(groups, vec![("ETHUSDT".to_string(), vec![root.join("ETHUSDT/2024/05/trades.parquet")])]);
+    }
+}
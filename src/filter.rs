@@ -0,0 +1,274 @@
+use anyhow::Result;
+use candle_generator::Trade;
+use csv::{Writer, WriterBuilder};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
+
+// Сколько цен должно накопиться в окне, прежде чем проверять отклонения
+const MIN_HISTORY: usize = 5;
+
+// Нижняя граница сигмы относительно медианы: после ровного участка (сигма 0) любое движение было бы выбросом
+const MIN_SIGMA_REL: f64 = 1e-4;
+
+#[derive(Debug, Clone)]
+pub struct FilterConfig {
+    /// Максимальное отклонение от медианы в стандартных отклонениях окна
+    pub max_sigma: Option<f64>,
+    /// Максимальное отклонение от медианы в процентах
+    pub max_deviation_pct: Option<f64>,
+    /// Размер скользящего окна (в трейдах) для медианы
+    pub window: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedTrade {
+    pub symbol: String,
+    pub source: String,
+    pub timestamp: i64,
+    pub id: String,
+    pub price: f64,
+    pub amount: f64,
+    pub reason: &'static str,
+    pub median: Option<f64>,
+}
+
+/// Скользящее окно цен: очередь в порядке поступления и отсортированная копия для медианы
+struct RollingWindow {
+    capacity: usize,
+    order: VecDeque<f64>,
+    sorted: Vec<f64>,
+}
+
+impl RollingWindow {
+    fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), order: VecDeque::new(), sorted: Vec::new() }
+    }
+
+    fn push(&mut self, price: f64) {
+        if self.order.len() == self.capacity {
+            if let Some(old) = self.order.pop_front() {
+                let pos = self.sorted.partition_point(|&p| p < old);
+                self.sorted.remove(pos);
+            }
+        }
+        let pos = self.sorted.partition_point(|&p| p < price);
+        self.sorted.insert(pos, price);
+        self.order.push_back(price);
+    }
+
+    /// Заменяет содержимое окна ценами `prices`
+    fn reseed(&mut self, prices: &[f64]) {
+        self.order.clear();
+        self.sorted.clear();
+        for &price in prices {
+            self.push(price);
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.order.len() >= MIN_HISTORY.min(self.capacity)
+    }
+
+    fn median(&self) -> f64 {
+        let n = self.sorted.len();
+        if n % 2 == 1 {
+            self.sorted[n / 2]
+        } else {
+            (self.sorted[n / 2 - 1] + self.sorted[n / 2]) / 2.0
+        }
+    }
+
+    /// Два прохода по окну: формула sum_sq/n - mean² теряет точность на ценах порядка BTC
+    fn std_dev(&self) -> f64 {
+        let n = self.order.len() as f64;
+        let mean = self.order.iter().sum::<f64>() / n;
+        (self.order.iter().map(|p| (p - mean) * (p - mean)).sum::<f64>() / n).sqrt()
+    }
+}
+
+/// Отбрасывает трейды с неположительной ценой/объёмом и выбросы относительно скользящей медианы.
+/// Окно пополняется только принятыми трейдами, чтобы серия выбросов не сдвигала медиану; если же
+/// выбросов подряд набирается половина окна, это считается сдвигом уровня: окно заполняется их ценами,
+/// а сами трейды серии возвращаются в принятые.
+pub fn filter_trades(trades: Vec<Trade>, cfg: &FilterConfig, symbol: &str, source: &str) -> (Vec<Trade>, Vec<RejectedTrade>) {
+    let (keep, rejected) = filter_mask(&trades, cfg, symbol, source);
+    (retain_by_mask(trades, &keep), rejected)
+}
+
+/// То же, что `filter_trades`, но возвращает маску принятых трейдов — для данных, идущих параллельно трейдам
+pub fn filter_mask(trades: &[Trade], cfg: &FilterConfig, symbol: &str, source: &str) -> (Vec<bool>, Vec<RejectedTrade>) {
+    let mut window = RollingWindow::new(cfg.window);
+    let mut keep = Vec::with_capacity(trades.len());
+    let mut rejected = Vec::new();
+    // Отклонённые подряд по медиане трейды: индекс в `trades` и в `rejected`
+    let mut outlier_run: Vec<(usize, usize)> = Vec::new();
+    let reseed_after = (cfg.window / 2).max(MIN_HISTORY);
+    for (idx, trade) in trades.iter().enumerate() {
+        let mut median = None;
+        let reason = if !trade.price.is_finite() || trade.price <= 0.0 {
+            Some("non_positive_price")
+        } else if !trade.amount.is_finite() || trade.amount <= 0.0 {
+            Some("non_positive_amount")
+        } else if window.ready() {
+            let m = window.median();
+            median = Some(m);
+            let deviation = (trade.price - m).abs();
+            if cfg.max_sigma.is_some_and(|n| deviation > n * window.std_dev().max(m * MIN_SIGMA_REL)) {
+                Some("sigma_deviation")
+            } else if cfg.max_deviation_pct.is_some_and(|pct| deviation > m * pct / 100.0) {
+                Some("pct_deviation")
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        keep.push(reason.is_none());
+        match reason {
+            Some(reason) => {
+                if median.is_some() {
+                    outlier_run.push((idx, rejected.len()));
+                }
+                rejected.push(RejectedTrade {
+                    symbol: symbol.to_string(),
+                    source: source.to_string(),
+                    timestamp: trade.timestamp.timestamp_millis(),
+                    id: trade.id.clone(),
+                    price: trade.price,
+                    amount: trade.amount,
+                    reason,
+                    median,
+                });
+                if outlier_run.len() >= reseed_after {
+                    // Сдвиг уровня: серия — настоящие сделки, окно переходит на их цены
+                    let prices: Vec<f64> = outlier_run.iter().map(|&(i, _)| trades[i].price).collect();
+                    window.reseed(&prices);
+                    for &(i, r) in outlier_run.iter().rev() {
+                        keep[i] = true;
+                        rejected.remove(r);
+                    }
+                    outlier_run.clear();
+                }
+            }
+            None => {
+                outlier_run.clear();
+                window.push(trade.price);
+            }
+        }
+    }
+    (keep, rejected)
+}
+
+pub fn retain_by_mask<T>(items: Vec<T>, keep: &[bool]) -> Vec<T> {
+    items.into_iter().zip(keep).filter(|(_, &k)| k).map(|(item, _)| item).collect()
+}
+
+pub fn rejects_writer<P: AsRef<Path>>(out_path: P) -> Result<Writer<File>> {
+    Ok(WriterBuilder::new().has_headers(true).from_path(out_path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_generator::{Instrument, Pair, MarketType, Side};
+    use chrono::{TimeZone, Utc};
+
+    fn sample_trade(ts: i64, price: f64, amount: f64) -> Trade {
+        Trade {
+            instrument: Instrument {
+                pair: Pair { base_id: "BTC".to_string(), quote_id: "USDT".to_string() },
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+            },
+            id: format!("{}", ts),
+            price,
+            amount,
+            side: Side::Buy,
+            timestamp: Utc.timestamp_millis_opt(ts).unwrap(),
+        }
+    }
+
+    fn config(max_sigma: Option<f64>, max_deviation_pct: Option<f64>) -> FilterConfig {
+        FilterConfig { max_sigma, max_deviation_pct, window: 10 }
+    }
+
+    #[test]
+    fn test_filter_non_positive() {
+        let trades = vec![
+            sample_trade(1714000000000, 50000.0, 0.1),
+            sample_trade(1714000001000, 0.0, 0.1),
+            sample_trade(1714000002000, 50000.0, -0.1),
+        ];
+        let (kept, rejected) = filter_trades(trades, &config(None, None), "BTCUSDT", "test");
+        assert_eq!(kept.len(), 1);
+        assert_eq!(rejected[0].reason, "non_positive_price");
+        assert_eq!(rejected[1].reason, "non_positive_amount");
+    }
+
+    #[test]
+    fn test_filter_pct_outlier() {
+        let mut trades: Vec<Trade> = (0..10).map(|i| sample_trade(1714000000000 + i * 1000, 50000.0 + i as f64, 0.1)).collect();
+        trades.push(sample_trade(1714000010000, 5000.0, 0.1));
+        trades.push(sample_trade(1714000011000, 50010.0, 0.1));
+        let (kept, rejected) = filter_trades(trades, &config(None, Some(5.0)), "BTCUSDT", "test");
+        assert_eq!(kept.len(), 11);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].reason, "pct_deviation");
+        assert_eq!(rejected[0].price, 5000.0);
+    }
+
+    #[test]
+    fn test_filter_sigma_outlier() {
+        let mut trades: Vec<Trade> = (0..10).map(|i| sample_trade(1714000000000 + i * 1000, 50000.0 + (i % 3) as f64, 0.1)).collect();
+        trades.push(sample_trade(1714000010000, 50100.0, 0.1));
+        let (kept, rejected) = filter_trades(trades, &config(Some(4.0), None), "BTCUSDT", "test");
+        assert_eq!(kept.len(), 10);
+        assert_eq!(rejected[0].reason, "sigma_deviation");
+    }
+
+    #[test]
+    fn test_filter_no_history() {
+        // Пока окно не накопилось, отклонения не проверяются
+        let trades = vec![
+            sample_trade(1714000000000, 50000.0, 0.1),
+            sample_trade(1714000001000, 100.0, 0.1),
+        ];
+        let (kept, rejected) = filter_trades(trades, &config(Some(1.0), Some(1.0)), "BTCUSDT", "test");
+        assert_eq!(kept.len(), 2);
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn test_filter_level_shift() {
+        // Ровный участок, затем уровень цены сдвигается на 20% и остаётся там
+        let mut trades: Vec<Trade> = (0..10).map(|i| sample_trade(1714000000000 + i * 1000, 50000.0, 0.1)).collect();
+        trades.push(sample_trade(1714000010000, 50000.5, 0.1));
+        trades.extend((0..20).map(|i| sample_trade(1714000011000 + i * 1000, 60000.0 + i as f64, 0.1)));
+        let (kept, rejected) = filter_trades(trades.clone(), &config(Some(4.0), None), "BTCUSDT", "test");
+        // Малое движение после ровного участка принято благодаря нижней границе сигмы;
+        // после половины окна выбросов подряд окно переходит на новый уровень, и вся серия принимается
+        assert!(rejected.is_empty());
+        assert_eq!(kept.len(), 31);
+        assert_eq!(kept[11].price, 60000.0);
+        assert_eq!(kept.last().unwrap().price, 60019.0);
+        let (kept, rejected) = filter_trades(trades.clone(), &config(None, Some(5.0)), "BTCUSDT", "test");
+        assert_eq!((kept.len(), rejected.len()), (31, 0));
+        // Короткий всплеск (меньше половины окна) по-прежнему отклоняется
+        let mut spike = trades[..11].to_vec();
+        spike.extend((0..3).map(|i| sample_trade(1714000011000 + i * 1000, 60000.0, 0.1)));
+        spike.push(sample_trade(1714000014000, 50000.0, 0.1));
+        let (kept, rejected) = filter_trades(spike, &config(Some(4.0), None), "BTCUSDT", "test");
+        assert_eq!((kept.len(), rejected.len()), (12, 3));
+    }
+
+    #[test]
+    fn test_std_dev_at_high_prices() {
+        let mut window = RollingWindow::new(4);
+        for p in [1e9 + 1.0, 1e9 - 1.0, 1e9 + 1.0, 1e9 - 1.0] {
+            window.push(p);
+        }
+        assert!((window.std_dev() - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,24 @@
+// Общие заготовки для тестов адаптеров и шаблонов вывода
+use candle_generator::{Candle, Instrument, MarketType, Pair, Timeframe};
+use chrono::TimeZone;
+
+/// Свеча BTC/USDT на binance spot: open = high = low = close, volume 1.5, 3 трейда
+pub fn sample_candle(tf: Timeframe, ts: i64, close: f64) -> Candle {
+    Candle {
+        instrument: Instrument {
+            pair: Pair { base_id: "BTC".to_string(), quote_id: "USDT".to_string() },
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+        },
+        interval: tf,
+        timestamp: chrono::Utc.timestamp_millis_opt(ts).unwrap(),
+        open: close,
+        high: close,
+        low: close,
+        close,
+        volume: 1.5,
+        trade_count: 3,
+        volume_usdt: None,
+        custom: std::collections::HashMap::new(),
+    }
+}
@@ -0,0 +1,283 @@
+use super::super::Args;
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::discovery::discover_inputs;
+use crate::pipeline::Pipeline;
+use crate::progress;
+use super::compression::{compression_by_magic, for_each_entry, read_magic, Compression};
+use super::csv::{sniff_csv_layout, CsvLayout};
+use super::mapping::CsvMapping;
+use super::{csv, duckdb, ipc, ndjson, parquet, sqlite};
+
+/// Что лежит в файле и каким адаптером его читать
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputKind {
+    Csv(CsvLayout),
+    /// JSON-объект трейда на строку
+    Ndjson,
+    Parquet,
+    /// Arrow IPC, файловый или потоковый формат
+    Arrow,
+    Duckdb,
+    Sqlite,
+    /// Сжатый файл; формат содержимого определяется после распаковки
+    Compressed(Compression),
+}
+
+/// Сколько байт читается в поисках первой строки: бинарный файл без '\n' не читается целиком
+const SNIFF_LIMIT: u64 = 64 * 1024;
+
+/// Файл `.csv` с незнакомым заголовком; с явной раскладкой (--preset, --csv-mapping) это не ошибка
+#[derive(Debug)]
+struct UnrecognizedCsvHeader {
+    source: PathBuf,
+    header: String,
+}
+
+impl fmt::Display for UnrecognizedCsvHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unrecognized CSV header in {:?}: {}", self.source, self.header)
+    }
+}
+
+impl std::error::Error for UnrecognizedCsvHeader {}
+
+/// `.csv`, в том числе сжатый: trades.csv.gz
+fn has_csv_extension(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let name = [".gz", ".zst", ".bz2"].iter().find_map(|c| name.strip_suffix(c)).unwrap_or(&name);
+    name.ends_with(".csv")
+}
+
+/// Определяет формат по сигнатуре, а для текстовых файлов — по имени (.jsonl/.ndjson) или первой строке:
+/// JSON-объект — NDJSON, иначе заголовок CSV.
+/// Файл `.csv` с незнакомым заголовком — ошибка, остальные нераспознанные файлы пропускаются.
+pub fn detect_input(path: &Path) -> Result<Option<InputKind>> {
+    let magic = read_magic::<16>(path)?;
+    if magic.starts_with(b"PAR1") {
+        return Ok(Some(InputKind::Parquet));
+    }
+    // Заголовок DuckDB: 8 байт контрольной суммы, затем "DUCK"
+    if magic.len() >= 12 && &magic[8..12] == b"DUCK" {
+        return Ok(Some(InputKind::Duckdb));
+    }
+    if sqlite::is_sqlite(&magic) {
+        return Ok(Some(InputKind::Sqlite));
+    }
+    if ipc::is_ipc_file(&magic) || ipc::is_ipc_stream(&magic) {
+        return Ok(Some(InputKind::Arrow));
+    }
+    match compression_by_magic(&magic) {
+        Compression::None => {}
+        compression => return Ok(Some(InputKind::Compressed(compression))),
+    }
+    let file = File::open(path).with_context(|| format!("cannot open {:?}", path))?;
+    let (header, _) = peek_header(file).with_context(|| format!("cannot read {:?}", path))?;
+    detect_text(path, header.as_deref())
+}
+
+/// Первая строка потока (не длиннее SNIFF_LIMIT, None — не UTF-8) и поток, в начало которого прочитанное возвращено
+fn peek_header(reader: impl Read) -> Result<(Option<String>, impl Read)> {
+    let mut reader = BufReader::new(reader);
+    let mut head = Vec::new();
+    (&mut reader).take(SNIFF_LIMIT).read_until(b'\n', &mut head)?;
+    Ok((String::from_utf8(head.clone()).ok(), Cursor::new(head).chain(reader)))
+}
+
+/// Формат текстового потока (файла или распакованной записи) по имени и первой строке; не UTF-8 — не текст
+fn detect_text(source: &Path, header: Option<&str>) -> Result<Option<InputKind>> {
+    let Some(header) = header else {
+        return Ok(None);
+    };
+    if ndjson::is_ndjson_file(source) || header.trim_start().starts_with('{') {
+        return Ok(Some(InputKind::Ndjson));
+    }
+    let layout = sniff_csv_layout(header);
+    if layout.is_none() && has_csv_extension(source) {
+        return Err(UnrecognizedCsvHeader { source: source.to_path_buf(), header: header.trim().to_string() }.into());
+    }
+    Ok(layout.map(InputKind::Csv))
+}
+
+/// С явной раскладкой заголовок .csv может быть любым, в том числе отсутствовать; ошибки чтения не подменяются
+fn apply_preset(detected: Result<Option<InputKind>>, preset: Option<CsvLayout>) -> Result<Option<InputKind>> {
+    match (detected, preset) {
+        (Err(err), Some(layout)) if err.is::<UnrecognizedCsvHeader>() => Ok(Some(InputKind::Csv(layout))),
+        (result, _) => result,
+    }
+}
+
+/// Прогоняет текстовый поток через CSV- или NDJSON-адаптер; нераспознанный поток пропускается
+fn process_text(
+    pipeline: &mut Pipeline,
+    symbol: &str,
+    source: &Path,
+    reader: &mut dyn Read,
+    kind: Option<InputKind>,
+    opts: &csv::CsvOptions,
+    mapping: &CsvMapping,
+) -> Result<()> {
+    let name = source.file_name().unwrap_or(source.as_os_str());
+    match kind {
+        Some(InputKind::Csv(layout)) => {
+            progress!("  File: {:?} (csv, {:?})", name, layout);
+            let opts = csv::CsvOptions { preset: opts.preset.or(Some(layout)), ..opts.clone() };
+            csv::process_csv_reader(pipeline, symbol, source, reader, &opts)
+        }
+        Some(InputKind::Ndjson) => {
+            progress!("  File: {:?} (ndjson)", name);
+            ndjson::process_ndjson_reader(pipeline, symbol, source, reader, mapping)
+        }
+        _ => {
+            progress!("  Skip: {:?} (unrecognized format)", name);
+            Ok(())
+        }
+    }
+}
+
+pub fn process_auto_batch(args: &Args) -> Result<()> {
+    let opts = csv::CsvOptions::from_args(args)?;
+    let ndjson_mapping = ndjson::ndjson_mapping(args)?;
+    let mut pipeline = Pipeline::new(args)?;
+    let inputs = discover_inputs(args, |_| true)?;
+    progress!("Batch symbols: {:?}", inputs.iter().map(|(symbol, _)| symbol).collect::<Vec<_>>());
+    for (symbol, files) in &inputs {
+        pipeline.begin_symbol(symbol, files.len());
+        for file_path in files {
+            let io_start = Instant::now();
+            let file_name = file_path.file_name().unwrap();
+            let trades = match apply_preset(detect_input(file_path), opts.preset)? {
+                kind @ (Some(InputKind::Csv(_)) | Some(InputKind::Ndjson) | None) => {
+                    process_text(&mut pipeline, symbol, file_path, &mut File::open(file_path)?, kind, &opts, &ndjson_mapping)?;
+                    continue;
+                }
+                Some(InputKind::Parquet) => {
+                    progress!("  File: {:?} (parquet)", file_name);
+                    parquet::read_trades_from_parquet(file_path)?
+                }
+                Some(InputKind::Arrow) => {
+                    progress!("  File: {:?} (arrow)", file_name);
+                    ipc::read_trades_from_ipc(file_path)?
+                }
+                Some(InputKind::Duckdb) => {
+                    progress!("  File: {:?} (duckdb)", file_name);
+                    duckdb::read_trades_from_duckdb(file_path, &args.duckdb_table, args.duckdb_where.as_deref())?
+                }
+                Some(InputKind::Sqlite) => {
+                    progress!("  File: {:?} (sqlite)", file_name);
+                    sqlite::read_trades_from_sqlite(file_path, &args.sqlite_table, args.sqlite_query.as_deref())?
+                }
+                Some(InputKind::Compressed(kind)) => {
+                    // Формат содержимого определяется после распаковки, для zip — по каждой записи отдельно
+                    progress!("  File: {:?} ({:?})", file_name, kind);
+                    for_each_entry(file_path, &[""], |source, reader| {
+                        let (header, mut reader) = peek_header(reader).with_context(|| format!("cannot read {:?}", source))?;
+                        let kind = apply_preset(detect_text(source, header.as_deref()), opts.preset)?;
+                        process_text(&mut pipeline, symbol, source, &mut reader, kind, &opts, &ndjson_mapping)
+                    })?;
+                    continue;
+                }
+            };
+            pipeline.stats.io_time += io_start.elapsed();
+            pipeline.process_trades(symbol, file_path, trades)?;
+        }
+    }
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_auto_{}_{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_detect_input_by_magic() {
+        let cases: [(&str, &[u8], Option<InputKind>); 10] = [
+            ("a.parquet", b"PAR1\x15\x04\x15", Some(InputKind::Parquet)),
+            ("a.feather", b"ARROW1\x00\x00", Some(InputKind::Arrow)),
+            ("a.db", b"SQLite format 3\x00", Some(InputKind::Sqlite)),
+            ("b.bin", b"\x01\x02\x03\x04\x05\x06\x07\x08DUCK\x40\x00\x00\x00", Some(InputKind::Duckdb)),
+            ("c.csv.gz", b"\x1f\x8b\x08\x00", Some(InputKind::Compressed(Compression::Gzip))),
+            ("d.csv.zst", b"\x28\xb5\x2f\xfd\x04", Some(InputKind::Compressed(Compression::Zstd))),
+            ("e.zip", b"PK\x03\x04\x14\x00", Some(InputKind::Compressed(Compression::Zip))),
+            ("f.txt", b"just some notes\n", None),
+            ("g.jsonl", b"{\"timestamp\":1714000000000,\"price\":1,\"amount\":1}\n", Some(InputKind::Ndjson)),
+            ("h.log", b"  {\"timestamp\":1714000000000}\n", Some(InputKind::Ndjson)),
+        ];
+        for (name, content, expected) in cases {
+            let path = temp_file(name, content);
+            let detected = detect_input(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            assert_eq!(detected, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_detect_input_csv_without_extension() {
+        let path = temp_file("trades_2024_04", b"timestamp,price,amount,side\n1714000000000,50000.0,0.1,buy\n");
+        let detected = detect_input(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(detected, Some(InputKind::Csv(CsvLayout::Native)));
+        let path = temp_file("klines.csv", b"open_time,open,high,low,close\n");
+        let detected = detect_input(&path);
+        std::fs::remove_file(&path).ok();
+        assert!(detected.as_ref().unwrap_err().is::<UnrecognizedCsvHeader>());
+        // Явная раскладка заменяет только ошибку заголовка, ошибки чтения проходят дальше
+        assert_eq!(apply_preset(detected, Some(CsvLayout::Native)).unwrap(), Some(InputKind::Csv(CsvLayout::Native)));
+        let missing = detect_input(Path::new("/nonexistent/trades.csv"));
+        assert!(apply_preset(missing, Some(CsvLayout::Native)).is_err());
+    }
+
+    #[test]
+    fn test_detect_input_reads_bounded_prefix() {
+        // Бинарный файл без перевода строки длиннее SNIFF_LIMIT
+        let path = temp_file("blob.bin", &vec![0x80u8; SNIFF_LIMIT as usize * 2]);
+        let detected = detect_input(&path).unwrap();
+        let (_, mut reader) = peek_header(File::open(&path).unwrap()).unwrap();
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(detected, None);
+        assert_eq!(content.len(), SNIFF_LIMIT as usize * 2);
+    }
+
+    #[test]
+    fn test_compressed_input_is_sniffed_after_decompression() {
+        use std::io::Write;
+        let root = std::env::temp_dir().join(format!("candle_batch_aggregator_auto_compressed_{}", std::process::id()));
+        let out = root.join("out");
+        std::fs::create_dir_all(root.join("in/BTCUSDT")).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(std::fs::File::create(root.join("in/BTCUSDT/day-1.jsonl.gz")).unwrap(), flate2::Compression::default());
+        encoder.write_all(b"{\"timestamp\":1714000020000,\"price\":50000.0,\"amount\":0.1}\n").unwrap();
+        encoder.finish().unwrap();
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(root.join("in/BTCUSDT/day-2.zip")).unwrap());
+        writer.start_file("day-2.csv", zip::write::FileOptions::default()).unwrap();
+        writer.write_all(b"timestamp,price,amount,side\n1714086420000,51000.0,0.2,buy\n").unwrap();
+        writer.start_file("README.txt", zip::write::FileOptions::default()).unwrap();
+        writer.write_all(b"exported trades\n").unwrap();
+        writer.finish().unwrap();
+        let args = Args {
+            input: root.join("in"),
+            output: Some(out.clone()),
+            output_template: "{stem}_{interval}.{ext}".to_string(),
+            ..Args::default()
+        };
+        let result = process_auto_batch(&args);
+        let ndjson_candles = std::fs::read_to_string(out.join("day-1_1m.csv"));
+        let csv_candles = std::fs::read_to_string(out.join("day-2_1m.csv"));
+        std::fs::remove_dir_all(&root).ok();
+        result.unwrap();
+        assert!(ndjson_candles.unwrap().contains("1714000020000,50000.0"));
+        assert!(csv_candles.unwrap().contains("1714086420000,51000.0"));
+    }
+}
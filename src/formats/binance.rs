@@ -0,0 +1,212 @@
+use anyhow::{bail, Context, Result};
+use candle_generator::{Trade, Instrument, Pair, MarketType, Side};
+use chrono::TimeZone;
+use csv::{ReaderBuilder, StringRecord};
+use std::io::Read;
+use std::path::{Component, Path};
+use crate::aggregation::TradeExtra;
+
+/// Выгрузки data.binance.vision: trades и aggTrades, с заголовком (фьючерсы) или без (спот)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinanceKind {
+    /// id, price, qty, quote_qty, time, is_buyer_maker[, is_best_match]
+    Trades,
+    /// agg_trade_id, price, quantity, first_trade_id, last_trade_id, transact_time, is_buyer_maker[, is_best_match]
+    AggTrades,
+}
+
+/// Начиная с 2025 года спотовые выгрузки (trades и klines) пишут время в микросекундах;
+/// миллисекунды сейчас ~1.7e12, микросекунды ~1.7e15
+pub fn binance_millis(raw: i64) -> i64 {
+    if raw.abs() >= 100_000_000_000_000 { raw / 1000 } else { raw }
+}
+
+/// Инструмент выгрузки: в строках его нет. Символ берётся из имени файла (`BTCUSDT-trades-2024-05-01.csv`,
+/// `BTCUSDT-aggTrades-2024-05.zip`), иначе — пара входной директории; как и у Tardis, символ не разбивается
+/// на base/quote и целиком идёт в base_id. Фьючерсы узнаются по пути data.binance.vision (`futures/um`, `futures/cm`)
+pub fn binance_instrument(source: &Path, symbol: &str) -> Instrument {
+    let file_symbol = source.components().rev().find_map(|c| {
+        let Component::Normal(name) = c else { return None };
+        let name = name.to_string_lossy();
+        let (prefix, rest) = name.split_once('-')?;
+        let rest = rest.to_lowercase();
+        (!prefix.is_empty() && (rest.starts_with("trades-") || rest.starts_with("aggtrades-"))).then(|| prefix.to_uppercase())
+    });
+    let futures = source.components().any(|c| {
+        let Component::Normal(name) = c else { return false };
+        matches!(name.to_string_lossy().to_lowercase().as_str(), "futures" | "um" | "cm")
+    });
+    Instrument {
+        pair: Pair { base_id: file_symbol.unwrap_or_else(|| symbol.to_string()), quote_id: String::new() },
+        exchange: "binance".to_string(),
+        market_type: if futures { MarketType::Futures } else { MarketType::Spot },
+    }
+}
+
+/// is_buyer_maker = true означает, что агрессор — продавец
+fn parse_buyer_maker(value: &str) -> Result<Side> {
+    match value.trim().to_lowercase().as_str() {
+        "true" => Ok(Side::Sell),
+        "false" => Ok(Side::Buy),
+        other => bail!("invalid is_buyer_maker value {:?}", other),
+    }
+}
+
+/// Определяет вид выгрузки по первой строке: по именам колонок заголовка или по позиции булевой колонки is_buyer_maker
+pub fn sniff_binance(first_line: &str) -> Option<BinanceKind> {
+    let fields: Vec<String> = first_line.trim().split(',').map(|f| f.trim().to_lowercase()).collect();
+    if fields.iter().any(|f| f == "quote_qty") {
+        return Some(BinanceKind::Trades);
+    }
+    if fields.iter().any(|f| f == "first_trade_id") {
+        return Some(BinanceKind::AggTrades);
+    }
+    if fields.first().is_some_and(|f| f.parse::<i64>().is_err()) {
+        return None;
+    }
+    match fields.iter().position(|f| f == "true" || f == "false") {
+        Some(5) => Some(BinanceKind::Trades),
+        Some(6) => Some(BinanceKind::AggTrades),
+        _ => None,
+    }
+}
+
+fn field<'r>(record: &'r StringRecord, idx: usize, name: &str) -> Result<&'r str> {
+    record.get(idx).with_context(|| format!("missing column {} at position {}", name, idx))
+}
+
+fn parse_record(record: &StringRecord, kind: BinanceKind, instrument: &Instrument) -> Result<(Trade, TradeExtra)> {
+    let num = |idx: usize, name: &str| -> Result<f64> {
+        let value = field(record, idx, name)?;
+        value.trim().parse::<f64>().with_context(|| format!("invalid {} {:?}", name, value))
+    };
+    let int = |idx: usize, name: &str| -> Result<i64> {
+        let value = field(record, idx, name)?;
+        value.trim().parse::<i64>().with_context(|| format!("invalid {} {:?}", name, value))
+    };
+    let price = num(1, "price")?;
+    let amount = num(2, "qty")?;
+    let (time, side, extra) = match kind {
+        BinanceKind::Trades => (
+            int(4, "time")?,
+            parse_buyer_maker(field(record, 5, "is_buyer_maker")?)?,
+            TradeExtra { quote_volume: Some(num(3, "quote_qty")?), trade_count: None },
+        ),
+        BinanceKind::AggTrades => {
+            let first = int(3, "first_trade_id")?;
+            let last = int(4, "last_trade_id")?;
+            (
+                int(5, "transact_time")?,
+                parse_buyer_maker(field(record, 6, "is_buyer_maker")?)?,
+                TradeExtra { quote_volume: Some(price * amount), trade_count: Some((last - first + 1).max(1) as u64) },
+            )
+        }
+    };
+    let ts = binance_millis(time);
+    let trade = Trade {
+        instrument: instrument.clone(),
+        id: field(record, 0, "id")?.trim().to_string(),
+        price,
+        amount,
+        side,
+        timestamp: chrono::Utc.timestamp_millis_opt(ts).single().with_context(|| format!("invalid time {}", time))?,
+    };
+    Ok((trade, extra))
+}
+
+/// Читает выгрузку Binance; первая строка считается заголовком, если её первое поле не число.
+/// `instrument` — см. `binance_instrument`
+pub fn read_binance_trades(reader: impl Read, kind: BinanceKind, instrument: &Instrument) -> Result<(Vec<Trade>, Vec<TradeExtra>)> {
+    let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(reader);
+    let mut trades = Vec::new();
+    let mut extras = Vec::new();
+    for (line, result) in rdr.records().enumerate() {
+        let record = result?;
+        if line == 0 && record.get(0).is_some_and(|f| f.trim().parse::<i64>().is_err()) {
+            continue;
+        }
+        let (trade, extra) = parse_record(&record, kind, instrument).with_context(|| format!("line {}", line + 1))?;
+        trades.push(trade);
+        extras.push(extra);
+    }
+    Ok((trades, extras))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_binance_spot_trades_headerless() {
+        // 2024: миллисекунды, 2025: микросекунды
+        let data = "1,50000.0,0.1,5000.0,1714000000000,True,True\n\
+                    2,50010.0,0.2,10002.0,1735689600000000,false,True\n";
+        let (trades, extras) = read_binance_trades(Cursor::new(data), BinanceKind::Trades, &binance_instrument(Path::new("BTCUSDT/trades.csv"), "BTCUSDT")).unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].side, Side::Sell);
+        assert_eq!(trades[1].side, Side::Buy);
+        assert_eq!(trades[1].timestamp.timestamp_millis(), 1735689600000);
+        assert_eq!(extras[1], TradeExtra { quote_volume: Some(10002.0), trade_count: None });
+    }
+
+    #[test]
+    fn test_read_binance_agg_trades_with_header() {
+        let data = "agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker\n\
+                    100,50000.0,0.5,1000,1004,1714000000000,false\n";
+        let (trades, extras) = read_binance_trades(Cursor::new(data), BinanceKind::AggTrades, &binance_instrument(Path::new("BTCUSDT/trades.csv"), "BTCUSDT")).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].id, "100");
+        assert_eq!(extras[0].trade_count, Some(5));
+        assert_eq!(extras[0].quote_volume, Some(25000.0));
+    }
+
+    #[test]
+    fn test_sniff_binance() {
+        assert_eq!(sniff_binance("1,50000.0,0.1,5000.0,1714000000000,True,True"), Some(BinanceKind::Trades));
+        assert_eq!(sniff_binance("100,50000.0,0.5,1000,1004,1714000000000,false,true"), Some(BinanceKind::AggTrades));
+        assert_eq!(sniff_binance("id,price,qty,quote_qty,time,is_buyer_maker"), Some(BinanceKind::Trades));
+        assert_eq!(sniff_binance("timestamp,price,amount,side"), None);
+    }
+
+    #[test]
+    fn test_binance_instrument() {
+        let spot = binance_instrument(Path::new("in/ALL/spot/daily/trades/ETHUSDT-trades-2024-05-01.zip/ETHUSDT-trades-2024-05-01.csv"), "ALL");
+        assert_eq!(spot.pair.base_id, "ETHUSDT");
+        assert_eq!(spot.market_type, MarketType::Spot);
+        let futures = binance_instrument(Path::new("data/futures/um/daily/aggTrades/BTCUSDT-aggTrades-2024-05-01.csv"), "X");
+        assert_eq!(futures.pair.base_id, "BTCUSDT");
+        assert_eq!(futures.market_type, MarketType::Futures);
+        // Имя файла без символа: пара берётся из директории
+        assert_eq!(binance_instrument(Path::new("SOLUSDT/part-1.csv"), "SOLUSDT").pair.base_id, "SOLUSDT");
+    }
+
+    #[test]
+    fn test_binance_symbols_do_not_collide_in_sink() {
+        let root = std::env::temp_dir().join(format!("candle_batch_aggregator_binance_{}", std::process::id()));
+        let input = root.join("in");
+        // Одинаковое время у двух пар: в общей таблице свечи не должны перезаписывать друг друга
+        for (symbol, price) in [("BTCUSDT", 50000.0), ("ETHUSDT", 3000.0)] {
+            let dir = input.join(symbol);
+            std::fs::create_dir_all(&dir).unwrap();
+            let data = format!("1,{},0.1,1.0,1714000000000,true,true\n2,{},0.2,1.0,1714000001000,false,true\n", price, price);
+            std::fs::write(dir.join(format!("{}-trades-2024-04-24.csv", symbol)), data).unwrap();
+        }
+        let args = crate::Args {
+            input: input.clone(),
+            output: Some(root.join("out")),
+            output_format: "sqlite".to_string(),
+            interval: "1".to_string(),
+            ..Default::default()
+        };
+        crate::formats::csv::process_csv_batch(&args).unwrap();
+        let conn = rusqlite::Connection::open(root.join("out/candles.sqlite")).unwrap();
+        let mut stmt = conn.prepare("SELECT exchange, base_id, close FROM candles ORDER BY base_id").unwrap();
+        let rows: Vec<(String, String, f64)> = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))).unwrap().map(|r| r.unwrap()).collect();
+        std::fs::remove_dir_all(&root).ok();
+        assert_eq!(
+            rows,
+            vec![("binance".to_string(), "BTCUSDT".to_string(), 50000.0), ("binance".to_string(), "ETHUSDT".to_string(), 3000.0)]
+        );
+    }
+}
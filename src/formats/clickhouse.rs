@@ -1,24 +1,477 @@
-// ВРЕМЕННО ОТКЛЮЧЕНО для прохождения тестов по CSV
-// Заготовка для batch-агрегации ClickHouse
-// TODO: Импортировать необходимые crates (clickhouse, polars, и т.д.)
-// use polars::prelude::*;
-// use clickhouse::Client;
+use super::super::Args;
+use anyhow::{bail, Context, Result};
+use candle_generator::{Candle, Trade, Instrument, Pair};
+use chrono::TimeZone;
+use csv::{ReaderBuilder, StringRecord};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, OnceCell};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use crate::pipeline::{Pipeline, resolve_symbols};
+use crate::progress;
+use super::{parse_market_type, parse_side, parse_time_bound, time_chunks};
 
-// use super::super::Args;
-// use anyhow::Result;
-// use candle_generator::{Trade, Instrument, Pair, MarketType, Side, Candle, Timeframe, CandleGenerator};
-// use std::fs;
-// use std::path::PathBuf;
-// use crate::aggregation;
-// use crate::stats::{ProcessingStats, print_summary};
-// use csv::ReaderBuilder;
+/// Строка TSV-выгрузки трейдов, колонки как в examples/clickhouse_batch.rs; время уже в миллисекундах
+#[derive(Debug, Deserialize)]
+struct ClickhouseTrade {
+    timestamp: i64,
+    exchange: String,
+    base_id: String,
+    quote_id: String,
+    market_type: String,
+    id: String,
+    price: f64,
+    amount: f64,
+    side: String,
+}
 
-// pub struct ClickhouseTrade {}
+fn quote_ident(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
 
-// pub fn read_trades_from_clickhouse(_conn_str: &str) -> Vec<ClickhouseTrade> { vec![] }
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
 
-// pub fn write_candles_to_clickhouse(_conn_str: &str, _candles: &[/*Candle*/]) {}
+/// Клиент HTTP-интерфейса ClickHouse (порт 8123): SQL в теле POST-запроса
+pub struct ClickhouseClient {
+    url: String,
+    database: String,
+    client: reqwest::blocking::Client,
+}
 
-// fn parse_intervals(interval_str: &str) -> Vec<Timeframe> { vec![] }
+impl ClickhouseClient {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        Ok(Self {
+            url: args.clickhouse_url.trim_end_matches('/').to_string(),
+            database: args.clickhouse_database.clone(),
+            client: reqwest::blocking::Client::builder().timeout(Duration::from_secs(300)).build()?,
+        })
+    }
 
-// pub fn process_clickhouse_batch(args: &Args) -> Result<()> { Ok(()) } 
\ No newline at end of file
+    /// Выполняет запрос и возвращает тело ответа целиком
+    pub fn query(&self, sql: &str) -> Result<String> {
+        let response = self
+            .client
+            .post(format!("{}/", self.url))
+            .query(&[("database", self.database.as_str())])
+            .body(sql.to_string())
+            .send()
+            .with_context(|| format!("cannot reach ClickHouse at {}", self.url))?;
+        let status = response.status();
+        let body = response.text()?;
+        if !status.is_success() {
+            bail!("ClickHouse query failed ({}): {}\n  query: {}", status, body.trim(), sql);
+        }
+        Ok(body)
+    }
+
+    /// INSERT с данными в теле запроса; одинаковый токен дедупликации не даёт вставить пачку дважды
+    pub fn insert(&self, sql: &str, data: String, dedup_token: &str) -> Result<()> {
+        let response = self
+            .client
+            .post(format!("{}/", self.url))
+            .query(&[
+                ("database", self.database.as_str()),
+                ("query", sql),
+                ("insert_deduplication_token", dedup_token),
+            ])
+            .body(data)
+            .send()
+            .with_context(|| format!("cannot reach ClickHouse at {}", self.url))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            bail!("ClickHouse insert failed ({}): {}\n  query: {}", status, response.text().unwrap_or_default().trim(), sql);
+        }
+        Ok(())
+    }
+}
+
+fn tsv_reader(body: &str) -> csv::Reader<&[u8]> {
+    ReaderBuilder::new().delimiter(b'\t').has_headers(false).quoting(false).from_reader(body.as_bytes())
+}
+
+/// Снимает экранирование формата TabSeparated: \t, \n, \\ и т.д.; \N (NULL) становится пустой строкой
+fn tsv_unescape(field: &str) -> String {
+    if field == "\\N" {
+        return String::new();
+    }
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Записи TSV-ответа с раскрытым экранированием
+fn tsv_records(body: &str) -> impl Iterator<Item = csv::Result<StringRecord>> + '_ {
+    tsv_reader(body).into_records().map(|r| r.map(|record| record.iter().map(tsv_unescape).collect()))
+}
+
+/// Трейды в таблице ClickHouse, читаемые окнами по времени в формате TabSeparated
+pub struct ClickhouseSource {
+    client: ClickhouseClient,
+    table: String,
+    timestamp_ms: String,
+    /// Пары (base_id, quote_id) таблицы, загружаются при первом обращении
+    pairs: OnceCell<Vec<(String, String)>>,
+}
+
+impl ClickhouseSource {
+    /// Проверяет таблицу: `timestamp` может быть Int64 (мс) или DateTime/DateTime64
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let client = ClickhouseClient::from_args(args)?;
+        let table = args.clickhouse_table.clone();
+        let sql = format!(
+            "SELECT type FROM system.columns WHERE database = currentDatabase() AND table = {} AND name = 'timestamp' FORMAT TSV",
+            quote_literal(&table)
+        );
+        let ts_type = client.query(&sql)?.trim().to_string();
+        if ts_type.is_empty() {
+            bail!("table {:?} not found or has no timestamp column", table);
+        }
+        let timestamp_ms = if ts_type.starts_with("DateTime") {
+            "toUnixTimestamp64Milli(toDateTime64(timestamp, 3))".to_string()
+        } else {
+            "toInt64(timestamp)".to_string()
+        };
+        Ok(Self { client, table, timestamp_ms, pairs: OnceCell::new() })
+    }
+
+    /// Все пары (base_id, quote_id) таблицы; один DISTINCT по колонкам LowCardinality за прогон
+    fn pairs(&self) -> Result<&[(String, String)]> {
+        if let Some(pairs) = self.pairs.get() {
+            return Ok(pairs);
+        }
+        let sql = format!("SELECT DISTINCT base_id, quote_id FROM {} ORDER BY 1, 2 FORMAT TSV", quote_ident(&self.table));
+        let body = self.client.query(&sql)?;
+        let mut pairs = Vec::new();
+        for record in tsv_records(&body) {
+            let record = record?;
+            pairs.push((record.get(0).unwrap_or_default().to_string(), record.get(1).unwrap_or_default().to_string()));
+        }
+        Ok(self.pairs.get_or_init(|| pairs))
+    }
+
+    /// Все пары в таблице (для `--symbol ALL`), как имена директорий пар: BTC + USDT = BTCUSDT
+    pub fn symbols(&self) -> Result<Vec<String>> {
+        let mut symbols: Vec<String> = self.pairs()?.iter().map(|(base, quote)| format!("{}{}", base, quote)).collect();
+        symbols.dedup();
+        Ok(symbols)
+    }
+
+    /// Условие на пару по колонкам ключа сортировки: фильтр по выражению concat(base_id, quote_id)
+    /// не даёт ClickHouse отсекать гранулы по первичному ключу. None — такой пары в таблице нет
+    fn symbol_condition(&self, symbol: &str) -> Result<Option<String>> {
+        let conditions: Vec<String> = self
+            .pairs()?
+            .iter()
+            .filter(|(base, quote)| format!("{}{}", base, quote) == symbol)
+            .map(|(base, quote)| format!("(base_id = {} AND quote_id = {})", quote_literal(base), quote_literal(quote)))
+            .collect();
+        Ok((!conditions.is_empty()).then(|| conditions.join(" OR ")))
+    }
+
+    /// Диапазон времени трейдов пары в миллисекундах [min, max + 1), None если трейдов нет
+    pub fn bounds(&self, symbol: &str) -> Result<Option<(i64, i64)>> {
+        let Some(condition) = self.symbol_condition(symbol)? else { return Ok(None) };
+        let sql = format!(
+            "SELECT count(), min({ts}), max({ts}) FROM {} WHERE {} FORMAT TSV",
+            quote_ident(&self.table),
+            condition,
+            ts = self.timestamp_ms
+        );
+        let body = self.client.query(&sql)?;
+        let Some(record) = tsv_records(&body).next().transpose()? else { return Ok(None) };
+        let (count, lo, hi): (u64, i64, i64) = record.deserialize(None).context("unexpected ClickHouse response")?;
+        Ok((count > 0).then_some((lo, hi + 1)))
+    }
+
+    /// Трейды пары за полуинтервал [from, to) в миллисекундах, по возрастанию времени
+    pub fn read_trades(&self, symbol: &str, from: i64, to: i64) -> Result<Vec<Trade>> {
+        let Some(condition) = self.symbol_condition(symbol)? else { return Ok(Vec::new()) };
+        let sql = format!(
+            "SELECT {ts}, exchange, base_id, quote_id, market_type, id, price, amount, side FROM {} \
+             WHERE ({}) AND {ts} >= {} AND {ts} < {} ORDER BY {ts} FORMAT TSV",
+            quote_ident(&self.table),
+            condition,
+            from,
+            to,
+            ts = self.timestamp_ms
+        );
+        let body = self.client.query(&sql)?;
+        let mut trades = Vec::new();
+        for record in tsv_records(&body) {
+            let row: ClickhouseTrade = record
+                .and_then(|r| r.deserialize(None))
+                .with_context(|| format!("unexpected ClickHouse response for {}", symbol))?;
+            trades.push(Trade {
+                instrument: Instrument {
+                    pair: Pair {
+                        base_id: row.base_id,
+                        quote_id: row.quote_id,
+                    },
+                    exchange: row.exchange,
+                    market_type: parse_market_type(&row.market_type),
+                },
+                id: row.id,
+                price: row.price,
+                amount: row.amount,
+                side: parse_side(&row.side),
+                timestamp: chrono::Utc
+                    .timestamp_millis_opt(row.timestamp)
+                    .single()
+                    .with_context(|| format!("invalid timestamp {} for {}", row.timestamp, symbol))?,
+            });
+        }
+        Ok(trades)
+    }
+}
+
+const CANDLE_COLUMNS: &str =
+    "exchange, market_type, base_id, quote_id, interval, timestamp, open, high, low, close, volume, trade_count, volume_usdt";
+
+fn tsv_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+/// Строка TSV для INSERT; время в виде DateTime64(3), отсутствующий volume_usdt — \N
+fn candle_to_tsv(candle: &Candle) -> String {
+    format!(
+        "{}\t{:?}\t{}\t{}\t{:?}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        tsv_escape(&candle.instrument.exchange),
+        candle.instrument.market_type,
+        tsv_escape(&candle.instrument.pair.base_id),
+        tsv_escape(&candle.instrument.pair.quote_id),
+        candle.interval,
+        candle.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+        candle.open,
+        candle.high,
+        candle.low,
+        candle.close,
+        candle.volume,
+        candle.trade_count,
+        candle.volume_usdt.map_or_else(|| "\\N".to_string(), |v| v.to_string()),
+    )
+}
+
+/// Сколько последних вставок нереплицированной таблицы помнит ClickHouse для `insert_deduplication_token`
+const DEDUP_WINDOW: u32 = 1000;
+
+/// Токен дедупликации пачки: SHA-256 содержимого, стабильный между версиями Rust и машинами,
+/// поэтому повторный прогон даёт тот же токен
+fn batch_token(table: &str, symbol: &str, rows: &str) -> String {
+    let digest: String = Sha256::digest(rows.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}", table, symbol, digest)
+}
+
+/// Запись свечей в ClickHouse: таблица ReplacingMergeTree с ключом сортировки по инструменту,
+/// интервалу и времени, так что повторные прогоны заменяют строки после слияния
+pub struct ClickhouseSink {
+    client: ClickhouseClient,
+    table: String,
+    batch_size: usize,
+    /// Таблица уже создана этим sink'ом: DDL отправляется один раз за прогон
+    table_ready: Cell<bool>,
+}
+
+impl ClickhouseSink {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        if args.clickhouse_batch_size == 0 {
+            bail!("--clickhouse-batch-size must be positive");
+        }
+        Ok(Self {
+            client: ClickhouseClient::from_args(args)?,
+            table: args.clickhouse_candles_table.clone(),
+            batch_size: args.clickhouse_batch_size,
+            table_ready: Cell::new(false),
+        })
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    fn ensure_table(&self) -> Result<()> {
+        if self.table_ready.get() {
+            return Ok(());
+        }
+        self.client.query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                exchange LowCardinality(String),
+                market_type LowCardinality(String),
+                base_id LowCardinality(String),
+                quote_id LowCardinality(String),
+                interval LowCardinality(String),
+                timestamp DateTime64(3, 'UTC'),
+                open Float64,
+                high Float64,
+                low Float64,
+                close Float64,
+                volume Float64,
+                trade_count UInt64,
+                volume_usdt Nullable(Float64),
+                updated_at DateTime64(3, 'UTC') DEFAULT now64(3)
+            ) ENGINE = ReplacingMergeTree(updated_at)
+            ORDER BY (exchange, market_type, base_id, quote_id, interval, timestamp)
+            SETTINGS non_replicated_deduplication_window = {}",
+            quote_ident(&self.table),
+            DEDUP_WINDOW
+        ))?;
+        self.table_ready.set(true);
+        Ok(())
+    }
+
+    /// Создаёт таблицу при первой записи и вставляет свечи пачками по `batch_size`
+    pub fn write_candles(&self, candles: &[Candle], symbol: &str) -> Result<()> {
+        if candles.is_empty() {
+            return Ok(());
+        }
+        self.ensure_table()?;
+        let sql = format!("INSERT INTO {} ({}) FORMAT TSV", quote_ident(&self.table), CANDLE_COLUMNS);
+        for batch in candles.chunks(self.batch_size) {
+            let rows: String = batch.iter().map(candle_to_tsv).collect();
+            let token = batch_token(&self.table, symbol, &rows);
+            self.client.insert(&sql, rows, &token)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn process_clickhouse_batch(args: &Args) -> Result<()> {
+    if args.chunk_hours == 0 {
+        bail!("--chunk-hours must be positive");
+    }
+    let mut pipeline = Pipeline::new(args)?;
+    let source = ClickhouseSource::from_args(args)?;
+    let symbols = if args.symbol()?.to_uppercase() == "ALL" { source.symbols()? } else { resolve_symbols(args)? };
+    progress!("Batch symbols: {:?}", symbols);
+    let from = args.from.as_deref().map(parse_time_bound).transpose()?.map(|t| t.timestamp_millis());
+    let to = args.to.as_deref().map(parse_time_bound).transpose()?.map(|t| t.timestamp_millis());
+    let step = args.chunk_hours as i64 * 3_600_000;
+    for symbol in &symbols {
+        let (start, end) = match (from, to) {
+            (Some(start), Some(end)) => (start, end),
+            _ => match source.bounds(symbol)? {
+                Some((lo, hi)) => (from.unwrap_or(lo), to.unwrap_or(hi)),
+                None => {
+                    progress!("\nNo trades for {} in {:?}", symbol, args.clickhouse_table);
+                    continue;
+                }
+            },
+        };
+        let chunks = time_chunks(start, end, step);
+        pipeline.begin_symbol(symbol, chunks.len());
+        let io_start = Instant::now();
+        let mut trades = Vec::new();
+        for (lo, hi) in chunks {
+            let chunk = source.read_trades(symbol, lo, hi)?;
+            if args.progress {
+                progress!("  Chunk: {} .. {} ({} trades)", lo, hi, chunk.len());
+            }
+            trades.extend(chunk);
+        }
+        pipeline.stats.io_time += io_start.elapsed();
+        pipeline.process_trades(symbol, Path::new(&args.clickhouse_table), trades)?;
+    }
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample_candle;
+    use candle_generator::Timeframe;
+    use crate::formats::mock_http::mock_server;
+
+    fn mock_args(url: String) -> Args {
+        Args { clickhouse_url: url, ..Default::default() }
+    }
+
+    #[test]
+    fn test_read_trades_from_clickhouse_mock() {
+        let tsv = "1714000000000\tbinance\tBTC\tUSDT\tSpot\tt1\\tx\t50000\t0.1\tBuy\n\
+                   1714000060000\tbinance\tBTC\tUSDT\tFutures\tt2\t50100.5\t0.2\tSell\n";
+        let pairs = "BTC\tUSDT\nETH\tUSDT\n";
+        let (url, server) = mock_server(vec![(200, "Int64\n".to_string()), (200, pairs.to_string()), (200, tsv.to_string())]);
+        let source = ClickhouseSource::from_args(&mock_args(url)).unwrap();
+        let trades = source.read_trades("BTCUSDT", 1714000000000, 1714003600000).unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].id, "t1\tx");
+        assert_eq!(trades[1].id, "t2");
+        assert_eq!(trades[1].price, 50100.5);
+        assert_eq!(trades[1].instrument.market_type, candle_generator::MarketType::Futures);
+        assert_eq!(trades[0].timestamp.timestamp_millis(), 1714000000000);
+        assert!(requests[1].1.starts_with("SELECT DISTINCT base_id, quote_id"));
+        assert!(requests[2].0.starts_with("POST /?database=default"));
+        assert!(requests[2].1.contains("WHERE ((base_id = 'BTC' AND quote_id = 'USDT')) AND toInt64(timestamp) >= 1714000000000"));
+        assert!(requests[2].1.ends_with("FORMAT TSV"));
+    }
+
+    #[test]
+    fn test_clickhouse_bounds_and_errors() {
+        let (url, server) = mock_server(vec![
+            (200, "DateTime64(3)\n".to_string()),
+            (200, "BTC\tUSDT\nBTCU\tSDT\n".to_string()),
+            (200, "2\t1714000000000\t1714000060000\n".to_string()),
+            (404, "Code: 60. DB::Exception: Table default.trades does not exist.".to_string()),
+        ]);
+        let source = ClickhouseSource::from_args(&mock_args(url)).unwrap();
+        assert_eq!(source.bounds("BTCUSDT").unwrap(), Some((1714000000000, 1714000060001)));
+        // Пары нет в таблице: запрос не отправляется
+        assert_eq!(source.bounds("ETHUSDT").unwrap(), None);
+        assert_eq!(source.symbols().unwrap(), vec!["BTCUSDT"]);
+        let err = source.read_trades("BTCUSDT", 0, 1).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+        let requests = server.join().unwrap();
+        assert!(requests[2].1.contains("toUnixTimestamp64Milli"));
+        assert!(requests[2].1.contains("(base_id = 'BTC' AND quote_id = 'USDT') OR (base_id = 'BTCU' AND quote_id = 'SDT')"));
+    }
+
+    #[test]
+    fn test_candle_to_tsv() {
+        assert_eq!(
+            candle_to_tsv(&sample_candle(Timeframe::m1, 1714000020000, 50000.5)),
+            "binance\tSpot\tBTC\tUSDT\tm1\t2024-04-24 23:07:00.000\t50000.5\t50000.5\t50000.5\t50000.5\t1.5\t3\t\\N\n"
+        );
+    }
+
+    #[test]
+    fn test_write_candles_to_clickhouse_batches() {
+        let (url, server) = mock_server(vec![(200, String::new()); 4]);
+        let args = Args { clickhouse_url: url, clickhouse_batch_size: 2, ..Default::default() };
+        let sink = ClickhouseSink::from_args(&args).unwrap();
+        let candles: Vec<_> = (0..3).map(|i| sample_candle(Timeframe::m1, 1714000020000 + i * 60_000, 50000.0)).collect();
+        sink.write_candles(&candles, "BTCUSDT").unwrap();
+        // Вторая запись того же sink'а не повторяет DDL
+        sink.write_candles(&candles[..2], "BTCUSDT").unwrap();
+        let requests = server.join().unwrap();
+        assert!(requests[0].1.contains("ENGINE = ReplacingMergeTree"));
+        assert!(requests[0].1.contains("non_replicated_deduplication_window = 1000"));
+        assert_eq!(requests[1].0, requests[3].0);
+        assert_eq!(requests[1].1.lines().count(), 2);
+        assert_eq!(requests[2].1.lines().count(), 1);
+        assert!(requests[1].0.contains("insert_deduplication_token=candles-BTCUSDT-"));
+        // Повторный прогон тех же свечей даёт те же токены
+        let rows: String = candles[..2].iter().map(candle_to_tsv).collect();
+        assert!(requests[1].0.contains(&batch_token("candles", "BTCUSDT", &rows)));
+        assert!(batch_token("candles", "BTCUSDT", "").ends_with("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+        assert_ne!(batch_token("candles", "BTCUSDT", &rows), batch_token("candles", "BTCUSDT", &requests[2].1));
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+/// Сжатие входного файла, определяется по сигнатуре
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Zip,
+}
+
+/// Расширения сжатых файлов, которые отбрасываются при выборе имени выходного файла
+const COMPRESSED_EXTENSIONS: [&str; 4] = ["gz", "zst", "bz2", "zip"];
+
+pub fn compression_by_magic(magic: &[u8]) -> Compression {
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Compression::Gzip
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Compression::Zstd
+    } else if magic.starts_with(b"BZh") {
+        Compression::Bzip2
+    } else if magic.starts_with(b"PK\x03\x04") {
+        Compression::Zip
+    } else {
+        Compression::None
+    }
+}
+
+/// Первые `N` байт файла (меньше, если файл короче)
+pub fn read_magic<const N: usize>(path: &Path) -> Result<Vec<u8>> {
+    let mut magic = [0u8; N];
+    let mut file = File::open(path)?;
+    let mut len = 0;
+    while len < N {
+        let n = file.read(&mut magic[len..])?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    Ok(magic[..len].to_vec())
+}
+
+pub fn detect_compression(path: &Path) -> Result<Compression> {
+    Ok(compression_by_magic(&read_magic::<4>(path)?))
+}
+
+/// Имя выходного файла для источника: trades.csv.gz -> trades, BTCUSDT-trades-2024-04.zip -> BTCUSDT-trades-2024-04
+pub fn source_stem(source: &Path) -> String {
+    let name = source.file_name().unwrap_or(source.as_os_str()).to_string_lossy().to_string();
+    let name = match name.rsplit_once('.') {
+        Some((rest, ext)) if COMPRESSED_EXTENSIONS.contains(&ext.to_lowercase().as_str()) => rest.to_string(),
+        _ => name,
+    };
+    Path::new(&name).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or(name)
+}
+
+/// Потоковая распаковка одиночного файла (gzip/zstd/bz2) без временных файлов
+fn decoder(path: &Path, compression: Compression) -> Result<Box<dyn Read>> {
+    wrap_decoder(BufReader::new(File::open(path)?), compression)
+}
+
+fn wrap_decoder(reader: impl BufRead + 'static, compression: Compression) -> Result<Box<dyn Read>> {
+    Ok(match compression {
+        // MultiGzDecoder: архивы, склеенные через cat, содержат несколько gzip-потоков
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        Compression::None | Compression::Zip => Box::new(reader),
+    })
+}
+
+/// Распаковка потока без имени файла (stdin): сжатие определяется по первым байтам.
+/// Сигнатура дочитывается целиком (или до конца потока), даже если канал отдаёт данные по байту;
+/// затем она возвращается в начало потока
+pub fn decode_stream(mut reader: impl Read + 'static) -> Result<Box<dyn Read>> {
+    let mut magic = Vec::with_capacity(4);
+    (&mut reader).take(4).read_to_end(&mut magic)?;
+    let compression = compression_by_magic(&magic);
+    if compression == Compression::Zip {
+        // Оглавление zip лежит в конце файла, из потока его не прочитать
+        bail!("zip archives cannot be read from a stream, unpack them first");
+    }
+    wrap_decoder(BufReader::new(Cursor::new(magic).chain(reader)), compression)
+}
+
+/// Вызывает `f` для каждого источника в файле: для zip — для каждой записи архива с окончанием из
+/// `entry_suffixes` (источник — `<архив>/<запись>`), для остальных — один раз с распакованным потоком
+pub fn for_each_entry(path: &Path, entry_suffixes: &[&str], mut f: impl FnMut(&Path, &mut dyn Read) -> Result<()>) -> Result<()> {
+    let compression = detect_compression(path)?;
+    if compression != Compression::Zip {
+        let mut reader = decoder(path, compression)?;
+        return f(path, &mut reader);
+    }
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))
+        .with_context(|| format!("cannot open zip archive {:?}", path))?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry.name().to_lowercase();
+        if !entry.is_file() || !entry_suffixes.iter().any(|suffix| name.ends_with(suffix)) {
+            continue;
+        }
+        // Путь записи внутри архива сохраняется, чтобы одноимённые файлы разных директорий не совпали
+        // в имени выходного файла; пути с `..` и абсолютные пропускаются
+        let Some(entry_name) = entry.enclosed_name().map(Path::to_path_buf) else {
+            continue;
+        };
+        let source = path.join(entry_name);
+        f(&source, &mut entry)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    const CSV: &str = "timestamp,price,amount,side\n1714000000000,50000.0,0.1,buy\n";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("candle_batch_aggregator_compression_{}_{}", std::process::id(), name))
+    }
+
+    fn collect_entries(path: &Path) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        for_each_entry(path, &[".csv"], |source, reader| {
+            let mut content = String::new();
+            reader.read_to_string(&mut content)?;
+            entries.push((source.strip_prefix(path).unwrap().to_string_lossy().to_string(), content));
+            Ok(())
+        })
+        .unwrap();
+        std::fs::remove_file(path).ok();
+        entries
+    }
+
+    #[test]
+    fn test_for_each_entry_single_stream() {
+        let gz = temp_path("trades.csv.gz");
+        let mut encoder = flate2::write::GzEncoder::new(File::create(&gz).unwrap(), flate2::Compression::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let zst = temp_path("trades.csv.zst");
+        std::fs::write(&zst, zstd::encode_all(CSV.as_bytes(), 3).unwrap()).unwrap();
+        let bz2 = temp_path("trades.csv.bz2");
+        let mut encoder = bzip2::write::BzEncoder::new(File::create(&bz2).unwrap(), bzip2::Compression::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        for path in [gz, zst, bz2] {
+            let entries = collect_entries(&path);
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].1, CSV);
+        }
+    }
+
+    #[test]
+    fn test_for_each_entry_zip() {
+        let path = temp_path("trades-2024-04.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        for name in ["2024-04/trades-01.csv", "README.txt", "trades-01.csv", "../escape.csv"] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(CSV.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        let entries = collect_entries(&path);
+        let names: Vec<_> = entries.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["2024-04/trades-01.csv", "trades-01.csv"]);
+        assert!(entries.iter().all(|(_, content)| content == CSV));
+        // Одноимённые записи дают разные имена выходных файлов
+        let stem = |entry: &str| crate::discovery::nested_stem(&Path::new("BTCUSDT/trades-2024-04.zip").join(entry), "BTCUSDT", &source_stem(Path::new(entry)));
+        assert_eq!(stem(names[0]), "2024-04_trades-01");
+        assert_eq!(stem(names[1]), "trades-01");
+    }
+
+    #[test]
+    fn test_decode_stream() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        let gz = encoder.finish().unwrap();
+        for data in [gz, zstd::encode_all(CSV.as_bytes(), 3).unwrap(), CSV.as_bytes().to_vec()] {
+            let mut content = String::new();
+            decode_stream(std::io::Cursor::new(data)).unwrap().read_to_string(&mut content).unwrap();
+            assert_eq!(content, CSV);
+        }
+        assert!(decode_stream(std::io::Cursor::new(b"PK\x03\x04".to_vec())).is_err());
+    }
+
+    /// Поток, который отдаёт не больше одного байта за вызов read, как медленный канал
+    struct ByteByByte(std::io::Cursor<Vec<u8>>);
+
+    impl Read for ByteByByte {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_decode_stream_short_reads() {
+        let zst = zstd::encode_all(CSV.as_bytes(), 3).unwrap();
+        let mut content = String::new();
+        decode_stream(ByteByByte(std::io::Cursor::new(zst))).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, CSV);
+        assert!(decode_stream(ByteByByte(std::io::Cursor::new(b"PK\x03\x04".to_vec()))).is_err());
+        // Поток короче сигнатуры читается как есть
+        let mut content = String::new();
+        decode_stream(ByteByByte(std::io::Cursor::new(b"ab".to_vec()))).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "ab");
+    }
+
+    #[test]
+    fn test_source_stem() {
+        assert_eq!(source_stem(Path::new("BTCUSDT/trades.csv")), "trades");
+        assert_eq!(source_stem(Path::new("BTCUSDT/trades.csv.gz")), "trades");
+        assert_eq!(source_stem(Path::new("BTCUSDT/2024-04.zip/trades-01.csv")), "trades-01");
+        assert_eq!(source_stem(Path::new("trades")), "trades");
+    }
+}
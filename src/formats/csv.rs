@@ -1,20 +1,37 @@
 use super::super::Args;
-use anyhow::Result;
-use std::fs::{self, File};
-use std::path::{Path, PathBuf};
-use csv::ReaderBuilder;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use csv::{ReaderBuilder, StringRecord};
 use serde::Deserialize;
-use candle_generator::{Trade, Instrument, Pair, MarketType, Side};
+use candle_generator::{Trade, Instrument, Pair, MarketType};
 use std::time::Instant;
-use crate::aggregation;
-use crate::stats::{ProcessingStats, print_summary};
+use crate::discovery::discover_inputs;
+use crate::pipeline::Pipeline;
+use crate::progress;
+use crate::aggregation::TradeExtra;
+use super::binance::{binance_instrument, read_binance_trades, sniff_binance, BinanceKind};
+use super::compression::for_each_entry;
+use super::tardis::{is_tardis_header, parse_clock, read_tardis_trades, TardisClock};
+use super::mapping::CsvMapping;
+use super::parse_side;
 use chrono::TimeZone;
 
+const CSV_SUFFIXES: [&str; 5] = [".csv", ".csv.gz", ".csv.zst", ".csv.bz2", ".zip"];
+
+/// Распространённые варианты названий колонок в заголовке
+const TIMESTAMP_COLUMNS: [&str; 3] = ["timestamp", "time", "ts"];
+const AMOUNT_COLUMNS: [&str; 4] = ["amount", "qty", "quantity", "size"];
+
 #[derive(Debug, Deserialize)]
 struct CsvTrade {
+    #[serde(alias = "time", alias = "ts")]
     timestamp: i64,
     price: f64,
+    #[serde(alias = "qty", alias = "quantity", alias = "size")]
     amount: f64,
+    #[serde(default)]
     side: String,
     #[serde(default)]
     base: String,
@@ -38,17 +55,13 @@ impl CsvTrade {
             id: format!("{}", self.timestamp),
             price: self.price,
             amount: self.amount,
-            side: match self.side.to_lowercase().as_str() {
-                "buy" => Side::Buy,
-                "sell" => Side::Sell,
-                _ => Side::Unknown,
-            },
+            side: parse_side(&self.side),
             timestamp: chrono::Utc.timestamp_millis_opt(self.timestamp).unwrap(),
         }
     }
 }
 
-fn parse_intervals(interval_str: &str) -> Vec<candle_generator::Timeframe> {
+pub fn parse_intervals(interval_str: &str) -> Vec<candle_generator::Timeframe> {
     if interval_str.to_uppercase() == "ALL" {
         return vec![candle_generator::Timeframe::m1, candle_generator::Timeframe::m5, candle_generator::Timeframe::m15, candle_generator::Timeframe::m30, candle_generator::Timeframe::h1, candle_generator::Timeframe::h4, candle_generator::Timeframe::d1];
     }
@@ -67,62 +80,162 @@ fn parse_intervals(interval_str: &str) -> Vec<candle_generator::Timeframe> {
         .collect()
 }
 
-pub fn process_csv_batch(args: &Args) -> Result<()> {
-    let mut stats = ProcessingStats::new();
-    stats.start();
-    let intervals = parse_intervals(&args.interval);
-    let symbols: Vec<String> = if args.symbol.to_uppercase() == "ALL" {
-        fs::read_dir(&args.input)?
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
-            .map(|e| e.file_name().to_string_lossy().to_string())
-            .collect()
-    } else {
-        args.symbol.split(',').map(|s| s.trim().to_string()).collect()
+/// Раскладка колонок CSV с трейдами, определяемая по заголовку или заданная через `--preset`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsvLayout {
+    /// timestamp (мс), price, amount, [side, base, quote, exchange]
+    Native,
+    /// Выгрузки data.binance.vision
+    Binance(BinanceKind),
+    /// Нормализованные выгрузки Tardis `trades`
+    Tardis,
+    /// Произвольная раскладка из `--csv-mapping`
+    Mapped,
+}
+
+/// Настройки чтения CSV из аргументов CLI
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// Раскладка из `--preset`; None — определять по каждому файлу
+    pub preset: Option<CsvLayout>,
+    pub tardis_clock: TardisClock,
+    pub mapping: Option<CsvMapping>,
+}
+
+impl CsvOptions {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let mut preset = parse_preset(&args.preset)?;
+        let mapping = args.csv_mapping.as_deref().map(CsvMapping::load).transpose()?;
+        if mapping.is_some() {
+            if preset.is_some() {
+                bail!("--csv-mapping and --preset {} are mutually exclusive", args.preset);
+            }
+            preset = Some(CsvLayout::Mapped);
+        }
+        Ok(Self {
+            preset,
+            tardis_clock: parse_clock(&args.tardis_timestamp)?,
+            mapping,
+        })
+    }
+}
+
+/// Раскладка из `--preset`; None для auto (определять по каждому файлу)
+pub fn parse_preset(preset: &str) -> Result<Option<CsvLayout>> {
+    Ok(match preset.trim().to_lowercase().as_str() {
+        "auto" => None,
+        "native" => Some(CsvLayout::Native),
+        "binance-trades" => Some(CsvLayout::Binance(BinanceKind::Trades)),
+        "binance-aggtrades" => Some(CsvLayout::Binance(BinanceKind::AggTrades)),
+        "tardis" => Some(CsvLayout::Tardis),
+        other => bail!("unknown preset {:?} (expected auto, native, binance-trades, binance-aggtrades or tardis)", other),
+    })
+}
+
+/// Определяет раскладку по первой строке файла, None если это не похоже на трейды
+pub fn sniff_csv_layout(header: &str) -> Option<CsvLayout> {
+    // Binance и Tardis проверяются первыми: их заголовки подходят и под общие синонимы
+    if is_tardis_header(header) {
+        return Some(CsvLayout::Tardis);
+    }
+    if let Some(kind) = sniff_binance(header) {
+        return Some(CsvLayout::Binance(kind));
+    }
+    let columns: Vec<String> = header.trim().split(',').map(|c| c.trim().trim_matches('"').to_lowercase()).collect();
+    let has = |names: &[&str]| columns.iter().any(|c| names.contains(&c.as_str()));
+    if has(&TIMESTAMP_COLUMNS) && has(&["price"]) && has(&AMOUNT_COLUMNS) {
+        return Some(CsvLayout::Native);
+    }
+    None
+}
+
+pub fn read_trades_from_csv(path: &Path) -> Result<Vec<Trade>> {
+    read_trades_from_reader(File::open(path)?)
+}
+
+/// Чтение трейдов из потока (распакованный файл, запись zip-архива).
+/// Заголовок приводится к нижнему регистру, как при определении раскладки: `Time,Price,Qty` тоже читается
+pub fn read_trades_from_reader(reader: impl Read) -> Result<Vec<Trade>> {
+    let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(reader);
+    let headers: StringRecord = rdr.headers()?.iter().map(|h| h.trim().to_lowercase()).collect();
+    let mut trades = Vec::new();
+    for result in rdr.records() {
+        let csv_trade: CsvTrade = result?.deserialize(Some(&headers))?;
+        trades.push(csv_trade.to_trade());
+    }
+    Ok(trades)
+}
+
+/// Трейды в раскладке `layout`; для Binance также данные по каждому трейду (quote_qty, число сделок).
+/// `source` и `symbol` нужны раскладкам без инструмента в строках (Binance)
+pub fn read_trades_with_layout(
+    reader: impl Read,
+    layout: CsvLayout,
+    opts: &CsvOptions,
+    source: &Path,
+    symbol: &str,
+) -> Result<(Vec<Trade>, Option<Vec<TradeExtra>>)> {
+    Ok(match layout {
+        CsvLayout::Native => (read_trades_from_reader(reader)?, None),
+        CsvLayout::Binance(kind) => {
+            let (trades, extras) = read_binance_trades(reader, kind, &binance_instrument(source, symbol))?;
+            (trades, Some(extras))
+        }
+        CsvLayout::Tardis => (read_tardis_trades(reader, opts.tardis_clock)?, None),
+        CsvLayout::Mapped => (opts.mapping.as_ref().context("CSV mapping is not set")?.read_trades(reader)?, None),
+    })
+}
+
+/// Прогоняет через пайплайн CSV-файл (в том числе сжатый или zip с несколькими CSV).
+/// Раскладка — из `--preset`, иначе определяется по первой строке каждого источника.
+pub fn process_csv_file(pipeline: &mut Pipeline, symbol: &str, path: &Path, opts: &CsvOptions) -> Result<()> {
+    for_each_entry(path, &[".csv"], |source, reader| {
+        if source != path {
+            progress!("    Entry: {:?}", source.strip_prefix(path).unwrap_or(source));
+        }
+        process_csv_reader(pipeline, symbol, source, reader, opts)
+    })
+}
+
+/// Первая строка потока целиком (даже длиннее буфера) и поток, в начало которого она возвращена
+fn peek_first_line(reader: impl Read) -> Result<(String, impl Read)> {
+    let mut reader = BufReader::new(reader);
+    let mut first_line = Vec::new();
+    reader.read_until(b'\n', &mut first_line)?;
+    Ok((String::from_utf8_lossy(&first_line).to_string(), Cursor::new(first_line).chain(reader)))
+}
+
+/// Прогоняет через пайплайн один распакованный CSV-поток (файл, запись архива, stdin)
+pub fn process_csv_reader(pipeline: &mut Pipeline, symbol: &str, source: &Path, reader: &mut dyn Read, opts: &CsvOptions) -> Result<()> {
+    let io_start = Instant::now();
+    let (header, reader) = peek_first_line(reader)?;
+    let Some(layout) = opts.preset.or_else(|| sniff_csv_layout(&header)) else {
+        bail!("unrecognized CSV header in {:?}: {} (choose a layout with --preset)", source, header.trim());
     };
-    println!("Batch symbols: {:?}", symbols);
-    for symbol in &symbols {
-        let symbol_dir = args.input.join(symbol);
-        if !symbol_dir.exists() { continue; }
-        let files: Vec<_> = fs::read_dir(&symbol_dir)?
-            .filter_map(|e| e.ok())
-            .map(|e| e.path())
-            .filter(|p| p.extension().map_or(false, |ext| ext == "csv"))
-            .collect();
-        println!("\nProcessing symbol: {} ({} files)", symbol, files.len());
+    let (trades, extras) = read_trades_with_layout(reader, layout, opts, source, symbol).with_context(|| format!("cannot read {:?}", source))?;
+    pipeline.stats.io_time += io_start.elapsed();
+    pipeline.process_trades_with_extras(symbol, source, trades, extras)
+}
+
+/// CSV-файл, в том числе сжатый (.csv.gz, .csv.zst, .csv.bz2), или zip-архив с CSV
+fn is_csv_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    CSV_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+pub fn process_csv_batch(args: &Args) -> Result<()> {
+    let opts = CsvOptions::from_args(args)?;
+    let mut pipeline = Pipeline::new(args)?;
+    let inputs = discover_inputs(args, is_csv_file)?;
+    progress!("Batch symbols: {:?}", inputs.iter().map(|(symbol, _)| symbol).collect::<Vec<_>>());
+    for (symbol, files) in &inputs {
+        pipeline.begin_symbol(symbol, files.len());
         for file_path in files {
-            let io_start = Instant::now();
-            stats.add_file();
-            println!("  File: {:?}", file_path.file_name().unwrap());
-            let file = File::open(&file_path)?;
-            let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(file);
-            let mut trades = Vec::new();
-            for result in rdr.deserialize() {
-                let csv_trade: CsvTrade = result?;
-                trades.push(csv_trade.to_trade());
-            }
-            stats.io_time += io_start.elapsed();
-            stats.add_trades(trades.len());
-            println!("    Trades: {}", trades.len());
-            let agg_start = Instant::now();
-            let chain = aggregation::aggregate_trades_chain(trades.iter(), &intervals);
-            stats.aggregation_time += agg_start.elapsed();
-            for (tf, candles) in chain {
-                stats.add_candles(&format!("{:?}", tf), candles.len());
-                let out_dir = args.output.clone().unwrap_or_else(|| PathBuf::from("candles"));
-                let out_dir = out_dir.join(format!("{}_{}", symbol, format!("{:?}", tf)));
-                fs::create_dir_all(&out_dir)?;
-                let out_file = out_dir.join(format!("{}_{}.csv", file_path.file_stem().unwrap().to_string_lossy(), format!("{:?}", tf)));
-                let io_start = Instant::now();
-                aggregation::write_candles_csv(&candles, &out_file)?;
-                stats.io_time += io_start.elapsed();
-                println!("    [{:?}] Candles: {} -> {:?}", tf, candles.len(), out_file);
-            }
+            progress!("  File: {:?}", file_path.file_name().unwrap());
+            process_csv_file(&mut pipeline, symbol, file_path, &opts)?;
         }
     }
-    stats.stop();
-    print_summary(&stats);
-    Ok(())
+    pipeline.finish()
 }
 
 #[cfg(test)]
@@ -188,6 +301,52 @@ mod tests {
         assert_eq!(trades[1].instrument.pair.base_id, "ETH");
     }
 
+    #[test]
+    fn test_sniff_csv_layout() {
+        assert_eq!(sniff_csv_layout("timestamp,price,amount,side,base,quote,exchange"), Some(CsvLayout::Native));
+        assert_eq!(sniff_csv_layout("\"Time\",\"Price\",\"Qty\"\n"), Some(CsvLayout::Native));
+        assert_eq!(sniff_csv_layout("open_time,open,high,low,close"), None);
+        assert_eq!(
+            sniff_csv_layout("id,price,qty,quote_qty,time,is_buyer_maker"),
+            Some(CsvLayout::Binance(BinanceKind::Trades))
+        );
+        assert_eq!(parse_preset("binance-aggtrades").unwrap(), Some(CsvLayout::Binance(BinanceKind::AggTrades)));
+        assert!(parse_preset("kraken").is_err());
+        assert_eq!(
+            sniff_csv_layout("exchange,symbol,timestamp,local_timestamp,id,side,price,amount"),
+            Some(CsvLayout::Tardis)
+        );
+    }
+
+    #[test]
+    fn test_csv_header_aliases() {
+        let data = "time,price,qty\n1714000000000,50000.0,0.1\n";
+        let mut rdr = csv::ReaderBuilder::new().has_headers(true).from_reader(Cursor::new(data));
+        let trade = rdr.deserialize::<CsvTrade>().next().unwrap().unwrap().to_trade();
+        assert_eq!(trade.amount, 0.1);
+        assert_eq!(trade.side, candle_generator::Side::Unknown);
+        // Заголовок в другом регистре и в кавычках распознаётся и читается
+        let data = "\"Time\",\"Price\",\"Qty\",\"Side\"\n1714000000000,50000.0,0.1,sell\n";
+        assert_eq!(sniff_csv_layout(data), Some(CsvLayout::Native));
+        let trades = read_trades_from_reader(Cursor::new(data)).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].timestamp.timestamp_millis(), 1714000000000);
+        assert_eq!(trades[0].amount, 0.1);
+        assert_eq!(trades[0].side, candle_generator::Side::Sell);
+    }
+
+    #[test]
+    fn test_peek_first_line_longer_than_buffer() {
+        let extra: String = (0..2000).map(|i| format!(",extra_{}", i)).collect();
+        let data = format!("timestamp,price,amount{}\n1714000000000,50000.0,0.1\n", extra);
+        let (header, mut reader) = peek_first_line(Cursor::new(data.clone())).unwrap();
+        assert!(header.len() > 8192);
+        assert!(header.ends_with("extra_1999\n"));
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, data);
+    }
+
     #[test]
     fn test_parse_intervals() {
         let tfs = parse_intervals("1,5,15");
@@ -1,34 +1,326 @@
-// ВРЕМЕННО ОТКЛЮЧЕНО для прохождения тестов по CSV
-// Заготовка для batch-агрегации DuckDB
-use polars::prelude::*;
-use duckdb::Connection;
 use super::super::Args;
-use anyhow::Result;
-use duckdb::{Connection, Result as DuckResult};
-use candle_generator::{Trade, Instrument, Pair, MarketType, Side, Candle, Timeframe, CandleGenerator};
+use anyhow::{bail, Context, Result};
+use duckdb::{params, AccessMode, Config, Connection};
+use candle_generator::{Candle, Timeframe, Trade, Instrument, Pair};
+use chrono::TimeZone;
 use std::fs;
-use std::path::PathBuf;
-use crate::aggregation;
-use crate::stats::{ProcessingStats, print_summary};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::discovery::discover_inputs;
+use crate::pipeline::{Pipeline, has_extension};
+use crate::progress;
+use super::{parse_market_type, parse_side};
 
-pub struct DuckdbTrade {}
+/// Промежуточная таблица для пакетной записи свечей
+const STAGING_TABLE: &str = "candles_staging";
 
-pub fn read_trades_from_duckdb(_path: &str) -> Vec<DuckdbTrade> { vec![] }
+/// Колонки таблицы трейдов (как в examples/duckdb_batch.rs)
+const TRADE_COLUMNS: [&str; 9] = ["timestamp", "exchange", "base_id", "quote_id", "market_type", "id", "price", "amount", "side"];
 
-pub fn write_candles_to_duckdb(_path: &str, _candles: &[/*Candle*/]) {}
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
 
-fn parse_intervals(interval_str: &str) -> Vec<Timeframe> { vec![] }
+/// Выражение для времени в миллисекундах: BIGINT берётся как есть, TIMESTAMP переводится через epoch_ms
+fn timestamp_expr(conn: &Connection, table: &str) -> Result<String> {
+    let mut stmt = conn.prepare("SELECT column_name, data_type FROM information_schema.columns WHERE table_name = ?")?;
+    let columns: Vec<(String, String)> = stmt
+        .query_map([table], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    if columns.is_empty() {
+        bail!("table {:?} not found", table);
+    }
+    for name in TRADE_COLUMNS {
+        if !columns.iter().any(|(c, _)| c == name) {
+            bail!("table {:?} has no column {:?} (expected columns: {})", table, name, TRADE_COLUMNS.join(", "));
+        }
+    }
+    let ts_type = columns.iter().find(|(c, _)| c == "timestamp").map(|(_, t)| t.to_uppercase()).unwrap_or_default();
+    Ok(if ts_type.starts_with("TIMESTAMP") {
+        "epoch_ms(\"timestamp\")".to_string()
+    } else {
+        "CAST(\"timestamp\" AS BIGINT)".to_string()
+    })
+}
+
+/// Читает трейды из таблицы `table` базы DuckDB, опционально с условием WHERE
+pub fn read_trades_from_duckdb(path: &Path, table: &str, filter: Option<&str>) -> Result<Vec<Trade>> {
+    let config = Config::default().access_mode(AccessMode::ReadOnly)?;
+    let conn = Connection::open_with_flags(path, config).with_context(|| format!("cannot open DuckDB database {:?}", path))?;
+    let ts = timestamp_expr(&conn, table).with_context(|| format!("unsupported trades table in {:?}", path))?;
+    let mut sql = format!(
+        "SELECT {}, exchange, base_id, quote_id, market_type, id, CAST(price AS DOUBLE), CAST(amount AS DOUBLE), side FROM {}",
+        ts,
+        quote_ident(table)
+    );
+    if let Some(filter) = filter {
+        sql.push_str(&format!(" WHERE {}", filter));
+    }
+    sql.push_str(" ORDER BY 1");
+    let mut stmt = conn.prepare(&sql).with_context(|| format!("invalid query: {}", sql))?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Option<String>>(3)?,
+            row.get::<_, Option<String>>(4)?,
+            row.get::<_, Option<String>>(5)?,
+            row.get::<_, f64>(6)?,
+            row.get::<_, f64>(7)?,
+            row.get::<_, Option<String>>(8)?,
+        ))
+    })?;
+    let mut trades = Vec::new();
+    for row in rows {
+        let (timestamp, exchange, base_id, quote_id, market_type, id, price, amount, side) = row?;
+        trades.push(Trade {
+            instrument: Instrument {
+                pair: Pair {
+                    base_id: base_id.unwrap_or_default(),
+                    quote_id: quote_id.unwrap_or_default(),
+                },
+                exchange: exchange.unwrap_or_default(),
+                market_type: parse_market_type(&market_type.unwrap_or_default()),
+            },
+            id: id.unwrap_or_else(|| timestamp.to_string()),
+            price,
+            amount,
+            side: parse_side(&side.unwrap_or_default()),
+            timestamp: chrono::Utc
+                .timestamp_millis_opt(timestamp)
+                .single()
+                .with_context(|| format!("invalid timestamp {} in {:?}", timestamp, path))?,
+        });
+    }
+    Ok(trades)
+}
+
+/// Раскладка свечей в DuckDB (`--duckdb-layout`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuckdbLayout {
+    /// Одна таблица `candles` с колонкой interval
+    Single,
+    /// Таблица `candles_<tf>` на каждый таймфрейм
+    PerTimeframe,
+}
+
+impl DuckdbLayout {
+    pub fn parse(layout: &str) -> Result<Self> {
+        Ok(match layout {
+            "single" => Self::Single,
+            "per-timeframe" => Self::PerTimeframe,
+            other => bail!("unknown DuckDB layout {:?} (expected single or per-timeframe)", other),
+        })
+    }
+
+    fn table(self, tf: &Timeframe) -> String {
+        match self {
+            Self::Single => "candles".to_string(),
+            Self::PerTimeframe => format!("candles_{:?}", tf),
+        }
+    }
+}
+
+/// Свечи в базе DuckDB. Соединение открывается при первой записи и живёт до конца прогона;
+/// свечи идут через Appender в промежуточную таблицу и одним INSERT OR REPLACE ... SELECT в основную.
+/// Первичный ключ — инструмент, интервал и время, поэтому повторная обработка заменяет строки, а не дублирует их.
+pub struct DuckdbSink {
+    path: PathBuf,
+    layout: DuckdbLayout,
+    conn: Option<Connection>,
+}
 
-pub fn process_duckdb_batch(args: &Args) -> Result<()> { Ok(()) }
+impl DuckdbSink {
+    pub fn new(path: PathBuf, layout: DuckdbLayout) -> Self {
+        Self { path, layout, conn: None }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn connection(&mut self) -> Result<&mut Connection> {
+        if self.conn.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let conn = Connection::open(&self.path).with_context(|| format!("cannot open DuckDB database {:?}", self.path))?;
+            self.conn = Some(conn);
+        }
+        Ok(self.conn.as_mut().expect("connection is opened above"))
+    }
+
+    pub fn write_candles(&mut self, candles: &[Candle]) -> Result<()> {
+        let Some(first) = candles.first() else { return Ok(()) };
+        let table = quote_ident(&self.layout.table(&first.interval));
+        let conn = self.connection()?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                exchange TEXT NOT NULL,
+                market_type TEXT NOT NULL,
+                base_id TEXT NOT NULL,
+                quote_id TEXT NOT NULL,
+                interval TEXT NOT NULL,
+                timestamp TIMESTAMP NOT NULL,
+                open DOUBLE,
+                high DOUBLE,
+                low DOUBLE,
+                close DOUBLE,
+                volume DOUBLE,
+                trade_count BIGINT,
+                volume_usdt DOUBLE,
+                PRIMARY KEY (exchange, market_type, base_id, quote_id, interval, timestamp)
+            )",
+            table
+        ))?;
+        let tx = conn.transaction()?;
+        // Промежуточная таблица создаётся и удаляется в той же транзакции, время в ней — миллисекунды
+        tx.execute_batch(&format!("CREATE OR REPLACE TABLE {} AS SELECT * EXCLUDE (timestamp), 0::BIGINT AS timestamp FROM {} LIMIT 0", STAGING_TABLE, table))?;
+        {
+            // Appender сбрасывает строки в таблицу при освобождении
+            let mut appender = tx.appender(STAGING_TABLE)?;
+            for c in candles {
+                appender.append_row(params![
+                    c.instrument.exchange,
+                    format!("{:?}", c.instrument.market_type),
+                    c.instrument.pair.base_id,
+                    c.instrument.pair.quote_id,
+                    format!("{:?}", c.interval),
+                    c.open,
+                    c.high,
+                    c.low,
+                    c.close,
+                    c.volume,
+                    c.trade_count as i64,
+                    c.volume_usdt,
+                    c.timestamp.timestamp_millis(),
+                ])?;
+            }
+        }
+        tx.execute_batch(&format!(
+            "INSERT OR REPLACE INTO {} SELECT exchange, market_type, base_id, quote_id, interval, epoch_ms(timestamp), open, high, low, close, volume, trade_count, volume_usdt FROM {};
+             DROP TABLE {};",
+            table, STAGING_TABLE, STAGING_TABLE
+        ))?;
+        tx.commit()?;
+        Ok(())
+    }
+}
 
-pub fn write_candles_duckdb_csv<P: AsRef<std::path::Path>>(candles: &[Candle], out_path: P) -> Result<()> { Ok(()) }
+/// Записывает свечи в базу DuckDB одним вызовом (см. `DuckdbSink`)
+pub fn write_candles_to_duckdb(path: &Path, candles: &[Candle], layout: &str) -> Result<()> {
+    DuckdbSink::new(path.to_path_buf(), DuckdbLayout::parse(layout)?).write_candles(candles)
+}
+
+pub fn process_duckdb_batch(args: &Args) -> Result<()> {
+    let mut pipeline = Pipeline::new(args)?;
+    let inputs = discover_inputs(args, |p| has_extension(p, &["db", "duckdb"]))?;
+    progress!("Batch symbols: {:?}", inputs.iter().map(|(symbol, _)| symbol).collect::<Vec<_>>());
+    for (symbol, files) in &inputs {
+        pipeline.begin_symbol(symbol, files.len());
+        for file_path in files {
+            let io_start = Instant::now();
+            progress!("  File: {:?}", file_path.file_name().unwrap());
+            let trades = read_trades_from_duckdb(file_path, &args.duckdb_table, args.duckdb_where.as_deref())?;
+            pipeline.stats.io_time += io_start.elapsed();
+            pipeline.process_trades(symbol, file_path, trades)?;
+        }
+    }
+    pipeline.finish()
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::sample_candle;
+    use std::path::PathBuf;
+
+    fn create_trades_db(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_{}_{}.db", name, std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE trades (
+                timestamp BIGINT,
+                exchange TEXT,
+                base_id TEXT,
+                quote_id TEXT,
+                market_type TEXT,
+                id TEXT,
+                price DOUBLE,
+                amount DOUBLE,
+                side TEXT
+            );
+            INSERT INTO trades VALUES
+                (1714000060000, 'binance', 'BTC', 'USDT', 'Spot', 't2', 50100.0, 0.2, 'Sell'),
+                (1714000000000, 'binance', 'BTC', 'USDT', 'Spot', 't1', 50000.0, 0.1, 'Buy'),
+                (1714000120000, 'binance', 'BTC', 'USDT', 'Spot', 't3', 50200.0, 0.3, 'Buy');"
+        ).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_trades_from_duckdb() {
+        let path = create_trades_db("read");
+        let trades = read_trades_from_duckdb(&path, "trades", None).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].id, "t1");
+        assert_eq!(trades[0].side, candle_generator::Side::Buy);
+        assert_eq!(trades[2].price, 50200.0);
+    }
+
+    #[test]
+    fn test_read_trades_from_duckdb_where() {
+        let path = create_trades_db("where");
+        let trades = read_trades_from_duckdb(&path, "trades", Some("side = 'Buy'")).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(trades.len(), 2);
+    }
+
+    #[test]
+    fn test_write_candles_to_duckdb_upsert() {
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_sink_{}.duckdb", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let candles = vec![sample_candle(Timeframe::m1, 1714000020000, 50000.0), sample_candle(Timeframe::m1, 1714000080000, 50100.0)];
+        let mut sink = DuckdbSink::new(path.clone(), DuckdbLayout::Single);
+        sink.write_candles(&candles).unwrap();
+        // Повторная запись той же минуты с исправленной ценой через то же соединение
+        let rerun = vec![sample_candle(Timeframe::m1, 1714000080000, 50150.0)];
+        sink.write_candles(&rerun).unwrap();
+        drop(sink);
+        let conn = Connection::open(&path).unwrap();
+        let count: i64 = conn.query_row("SELECT count(*) FROM candles", [], |row| row.get(0)).unwrap();
+        let close: f64 = conn
+            .query_row("SELECT close FROM candles WHERE timestamp = epoch_ms(CAST(1714000080000 AS BIGINT))", [], |row| row.get(0))
+            .unwrap();
+        drop(conn);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(count, 2);
+        assert_eq!(close, 50150.0);
+    }
+
+    #[test]
+    fn test_write_candles_to_duckdb_per_timeframe() {
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_sink_tf_{}.duckdb", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        write_candles_to_duckdb(&path, &[sample_candle(Timeframe::m1, 1714000020000, 50000.0)], "per-timeframe").unwrap();
+        let conn = Connection::open(&path).unwrap();
+        let count: i64 = conn.query_row("SELECT count(*) FROM candles_m1", [], |row| row.get(0)).unwrap();
+        drop(conn);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(count, 1);
+        // Неизвестная раскладка — ошибка даже без свечей
+        assert!(write_candles_to_duckdb(&path, &[], "weekly").is_err());
+        std::fs::remove_file(&path).ok();
+    }
+
     #[test]
-    fn test_duckdb_dummy() {
-        // Заглушка: тест всегда проходит
-        assert_eq!(2 + 2, 4);
+    fn test_read_trades_from_duckdb_missing_table() {
+        let path = create_trades_db("missing");
+        let err = read_trades_from_duckdb(&path, "ticks", None).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert!(format!("{:#}", err).contains("table \"ticks\" not found"));
     }
-} 
\ No newline at end of file
+}
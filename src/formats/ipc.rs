@@ -0,0 +1,174 @@
+use super::super::Args;
+use anyhow::{Context, Result};
+use arrow::ipc::reader::{FileReader, StreamReader};
+use arrow::ipc::writer::{FileWriter, StreamWriter};
+use arrow::record_batch::RecordBatch;
+use candle_generator::{Candle, Trade};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::time::Instant;
+use crate::discovery::discover_inputs;
+use crate::pipeline::{Pipeline, has_extension};
+use crate::progress;
+use super::compression::read_magic;
+use super::parquet::{candles_to_record_batch, check_trade_schema, trades_from_batch};
+
+/// Расширения файлов Arrow IPC: file format (Feather v2) и stream format
+pub const IPC_EXTENSIONS: [&str; 4] = ["arrow", "feather", "ipc", "arrows"];
+
+/// Файловый формат начинается с "ARROW1"; поток — сразу с сообщения (маркер продолжения 0xFFFFFFFF)
+pub fn is_ipc_file(magic: &[u8]) -> bool {
+    magic.starts_with(b"ARROW1")
+}
+
+pub fn is_ipc_stream(magic: &[u8]) -> bool {
+    magic.starts_with(&[0xff, 0xff, 0xff, 0xff])
+}
+
+/// Читает трейды из Arrow IPC; file или stream формат определяется по сигнатуре
+pub fn read_trades_from_ipc(path: &Path) -> Result<Vec<Trade>> {
+    let file = File::open(path).with_context(|| format!("cannot open {:?}", path))?;
+    let batches: Box<dyn Iterator<Item = Result<RecordBatch, arrow::error::ArrowError>>> = if is_ipc_file(&read_magic::<6>(path)?) {
+        let reader = FileReader::try_new(file, None).with_context(|| format!("{:?} is not a valid Arrow IPC file", path))?;
+        check_trade_schema(&reader.schema()).with_context(|| format!("unsupported schema in {:?}", path))?;
+        Box::new(reader)
+    } else {
+        let reader = StreamReader::try_new(BufReader::new(file), None)
+            .with_context(|| format!("{:?} is not a valid Arrow IPC stream", path))?;
+        check_trade_schema(&reader.schema()).with_context(|| format!("unsupported schema in {:?}", path))?;
+        Box::new(reader)
+    };
+    let mut trades = Vec::new();
+    for batch in batches {
+        trades_from_batch(&batch?, &mut trades).with_context(|| format!("cannot read trades from {:?}", path))?;
+    }
+    Ok(trades)
+}
+
+/// Пишет свечи одним record batch'ем со схемой Parquet-вывода (включая колонки custom_*).
+/// `stream` — потоковый формат вместо файлового.
+pub fn write_candles_ipc<P: AsRef<Path>>(candles: &[Candle], out_path: P, stream: bool) -> Result<()> {
+    write_candles_ipc_to(candles, File::create(out_path)?, stream)
+}
+
+/// Как `write_candles_ipc`, но в произвольный поток (например, stdout)
+pub fn write_candles_ipc_to(candles: &[Candle], out: impl Write, stream: bool) -> Result<()> {
+    let batch = candles_to_record_batch(candles)?;
+    if stream {
+        let mut writer = StreamWriter::try_new(out, &batch.schema())?;
+        writer.write(&batch)?;
+        writer.finish()?;
+    } else {
+        let mut writer = FileWriter::try_new(out, &batch.schema())?;
+        writer.write(&batch)?;
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+pub fn process_ipc_batch(args: &Args) -> Result<()> {
+    let mut pipeline = Pipeline::new(args)?;
+    let inputs = discover_inputs(args, |p| has_extension(p, &IPC_EXTENSIONS))?;
+    progress!("Batch symbols: {:?}", inputs.iter().map(|(symbol, _)| symbol).collect::<Vec<_>>());
+    for (symbol, files) in &inputs {
+        pipeline.begin_symbol(symbol, files.len());
+        for file_path in files {
+            let io_start = Instant::now();
+            progress!("  File: {:?}", file_path.file_name().unwrap());
+            let trades = read_trades_from_ipc(file_path)?;
+            pipeline.stats.io_time += io_start.elapsed();
+            pipeline.process_trades(symbol, file_path, trades)?;
+        }
+    }
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{ArrayRef, AsArray, Float64Array, Int64Array, StringArray};
+    use arrow::datatypes::{Field, Float64Type, Schema};
+    use candle_generator::{Instrument, MarketType, Pair, Timeframe};
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("candle_batch_aggregator_ipc_{}_{}", std::process::id(), name))
+    }
+
+    fn trades_batch() -> RecordBatch {
+        let text = |v: &str| -> ArrayRef { Arc::new(StringArray::from(vec![v, v])) };
+        let columns: Vec<(&str, ArrayRef)> = vec![
+            ("timestamp", Arc::new(Int64Array::from(vec![1714000000000, 1714000060000]))),
+            ("exchange", text("binance")),
+            ("base_id", text("BTC")),
+            ("quote_id", text("USDT")),
+            ("market_type", text("Spot")),
+            ("id", Arc::new(StringArray::from(vec!["t1", "t2"]))),
+            ("price", Arc::new(Float64Array::from(vec![50000.0, 50100.0]))),
+            ("amount", Arc::new(Float64Array::from(vec![0.1, 0.2]))),
+            ("side", Arc::new(StringArray::from(vec!["Buy", "Sell"]))),
+        ];
+        let schema = Schema::new(columns.iter().map(|(n, a)| Field::new(*n, a.data_type().clone(), false)).collect::<Vec<_>>());
+        RecordBatch::try_new(Arc::new(schema), columns.into_iter().map(|(_, a)| a).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_read_trades_from_ipc_file_and_stream() {
+        let batch = trades_batch();
+        let file_path = temp_path("trades.arrow");
+        let mut writer = FileWriter::try_new(File::create(&file_path).unwrap(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        let stream_path = temp_path("trades.arrows");
+        let mut writer = StreamWriter::try_new(File::create(&stream_path).unwrap(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        assert!(is_ipc_stream(&read_magic::<4>(&stream_path).unwrap()));
+        for path in [file_path, stream_path] {
+            let trades = read_trades_from_ipc(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            assert_eq!(trades.len(), 2);
+            assert_eq!(trades[1].price, 50100.0);
+            assert_eq!(trades[1].side, candle_generator::Side::Sell);
+        }
+    }
+
+    #[test]
+    fn test_write_candles_ipc() {
+        let candle = Candle {
+            instrument: Instrument {
+                pair: Pair { base_id: "BTC".to_string(), quote_id: "USDT".to_string() },
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+            },
+            interval: Timeframe::m5,
+            timestamp: chrono::Utc.timestamp_millis_opt(1714000200000).unwrap(),
+            open: 50000.0,
+            high: 50100.0,
+            low: 49900.0,
+            close: 50050.0,
+            volume: 1.5,
+            trade_count: 3,
+            volume_usdt: Some(75000.0),
+            custom: HashMap::from([("vwap".to_string(), 50010.0)]),
+        };
+        for stream in [false, true] {
+            let path = temp_path(if stream { "candles.arrows" } else { "candles.arrow" });
+            write_candles_ipc(std::slice::from_ref(&candle), &path, stream).unwrap();
+            let batches: Vec<RecordBatch> = if stream {
+                StreamReader::try_new(File::open(&path).unwrap(), None).unwrap().collect::<Result<_, _>>().unwrap()
+            } else {
+                FileReader::try_new(File::open(&path).unwrap(), None).unwrap().collect::<Result<_, _>>().unwrap()
+            };
+            std::fs::remove_file(&path).ok();
+            assert_eq!(batches.len(), 1);
+            assert_eq!(batches[0].column_by_name("interval").unwrap().as_string::<i32>().value(0), "m5");
+            assert_eq!(batches[0].column_by_name("custom_vwap").unwrap().as_primitive::<Float64Type>().value(0), 50010.0);
+            assert_eq!(batches[0].column_by_name("volume_usdt").unwrap().as_primitive::<Float64Type>().value(0), 75000.0);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use anyhow::{bail, Context, Result};
+use candle_generator::{Trade, Instrument, Pair, MarketType, Side};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use csv::{ReaderBuilder, StringRecord};
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+
+/// Колонка по имени из заголовка или по номеру (с нуля)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl Column {
+    fn parse(value: &str) -> Self {
+        value.trim().parse().map(Column::Index).unwrap_or_else(|_| Column::Name(value.trim().to_string()))
+    }
+}
+
+/// Единица времени для числовых меток
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeUnit {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TimeUnit {
+    /// Угадывает единицу по величине: сейчас секунды ~1.7e9, миллисекунды ~1.7e12, микросекунды ~1.7e15
+    fn from_magnitude(value: f64) -> Self {
+        let value = value.abs();
+        if value < 1e11 {
+            TimeUnit::Seconds
+        } else if value < 1e14 {
+            TimeUnit::Millis
+        } else if value < 1e17 {
+            TimeUnit::Micros
+        } else {
+            TimeUnit::Nanos
+        }
+    }
+}
+
+/// Формат колонки времени
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimestampFormat {
+    Unit(TimeUnit),
+    /// Числовая метка, единица определяется по величине каждого значения
+    Auto,
+    Iso8601,
+    /// Шаблон chrono/strftime, например "%d.%m.%Y %H:%M:%S"
+    Strftime(String),
+}
+
+impl TimestampFormat {
+    pub fn parse(value: &str) -> Result<Self> {
+        Ok(match value.trim() {
+            "s" => TimestampFormat::Unit(TimeUnit::Seconds),
+            "ms" => TimestampFormat::Unit(TimeUnit::Millis),
+            "us" | "µs" => TimestampFormat::Unit(TimeUnit::Micros),
+            "ns" => TimestampFormat::Unit(TimeUnit::Nanos),
+            "auto" => TimestampFormat::Auto,
+            "iso8601" | "iso" => TimestampFormat::Iso8601,
+            pattern if pattern.contains('%') => TimestampFormat::Strftime(pattern.to_string()),
+            other => bail!("unknown time format {:?} (expected s, ms, us, ns, auto, iso8601 or a strftime pattern)", other),
+        })
+    }
+
+    /// Переводит значение колонки времени в миллисекунды от эпохи
+    pub fn to_millis(&self, value: &str) -> Result<i64> {
+        let value = value.trim();
+        match self {
+            TimestampFormat::Unit(unit) => numeric_millis(value, Some(*unit)),
+            TimestampFormat::Auto => numeric_millis(value, None),
+            TimestampFormat::Iso8601 => {
+                if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+                    return Ok(dt.timestamp_millis());
+                }
+                // Без часового пояса время считается UTC
+                ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+                    .iter()
+                    .find_map(|f| NaiveDateTime::parse_from_str(value, f).ok())
+                    .map(|dt| Utc.from_utc_datetime(&dt).timestamp_millis())
+                    .with_context(|| format!("invalid ISO 8601 time {:?}", value))
+            }
+            TimestampFormat::Strftime(pattern) => DateTime::parse_from_str(value, pattern)
+                .map(|dt| dt.timestamp_millis())
+                .or_else(|_| NaiveDateTime::parse_from_str(value, pattern).map(|dt| Utc.from_utc_datetime(&dt).timestamp_millis()))
+                .with_context(|| format!("time {:?} does not match {:?}", value, pattern)),
+        }
+    }
+}
+
+fn numeric_millis(value: &str, unit: Option<TimeUnit>) -> Result<i64> {
+    if let Ok(raw) = value.parse::<i64>() {
+        return Ok(match unit.unwrap_or_else(|| TimeUnit::from_magnitude(raw as f64)) {
+            TimeUnit::Seconds => raw.checked_mul(1000).with_context(|| format!("time {} s is out of range", raw))?,
+            TimeUnit::Millis => raw,
+            TimeUnit::Micros => raw.div_euclid(1000),
+            TimeUnit::Nanos => raw.div_euclid(1_000_000),
+        });
+    }
+    // Дробные значения, чаще всего секунды вида 1714000000.123
+    let raw: f64 = value.parse().with_context(|| format!("invalid numeric time {:?}", value))?;
+    let millis = match unit.unwrap_or_else(|| TimeUnit::from_magnitude(raw)) {
+        TimeUnit::Seconds => raw * 1e3,
+        TimeUnit::Millis => raw,
+        TimeUnit::Micros => raw / 1e3,
+        TimeUnit::Nanos => raw / 1e6,
+    };
+    // `as i64` молча насыщает большие значения и превращает NaN в 0
+    if !millis.is_finite() || millis.abs() >= i64::MAX as f64 {
+        bail!("time {:?} is out of range", value);
+    }
+    Ok(millis.floor() as i64)
+}
+
+fn parse_delimiter(value: &str) -> Result<u8> {
+    Ok(match value {
+        "tab" | "\\t" | "\t" => b'\t',
+        "comma" => b',',
+        "semicolon" => b';',
+        "pipe" => b'|',
+        "space" => b' ',
+        other if other.len() == 1 => other.as_bytes()[0],
+        other => bail!("invalid delimiter {:?} (expected a single ASCII character, tab, comma, semicolon, pipe or space)", other),
+    })
+}
+
+/// Декларативное описание CSV-файла с трейдами (`--csv-mapping`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvMapping {
+    pub delimiter: String,
+    pub header: bool,
+    pub timestamp: Column,
+    pub price: Column,
+    pub amount: Column,
+    pub side: Option<Column>,
+    pub id: Option<Column>,
+    pub exchange: Option<Column>,
+    pub base: Option<Column>,
+    pub quote: Option<Column>,
+    pub time_format: String,
+    /// Написания стороны покупки и продажи (без учёта регистра); прочие значения — Unknown
+    pub buy: Vec<String>,
+    pub sell: Vec<String>,
+}
+
+impl Default for CsvMapping {
+    fn default() -> Self {
+        Self {
+            delimiter: ",".to_string(),
+            header: true,
+            timestamp: Column::Name("timestamp".to_string()),
+            price: Column::Name("price".to_string()),
+            amount: Column::Name("amount".to_string()),
+            side: None,
+            id: None,
+            exchange: None,
+            base: None,
+            quote: None,
+            time_format: "ms".to_string(),
+            buy: vec!["buy".to_string(), "b".to_string(), "bid".to_string()],
+            sell: vec!["sell".to_string(), "s".to_string(), "ask".to_string()],
+        }
+    }
+}
+
+/// Текстовые значения полей одного трейда до разбора
+pub(crate) struct RawTrade<'a> {
+    pub timestamp: &'a str,
+    pub price: &'a str,
+    pub amount: &'a str,
+    pub side: Option<&'a str>,
+    pub id: Option<&'a str>,
+    pub exchange: Option<&'a str>,
+    pub base: Option<&'a str>,
+    pub quote: Option<&'a str>,
+}
+
+/// Номера колонок после сопоставления с заголовком
+struct Resolved {
+    timestamp: usize,
+    price: usize,
+    amount: usize,
+    side: Option<usize>,
+    id: Option<usize>,
+    exchange: Option<usize>,
+    base: Option<usize>,
+    quote: Option<usize>,
+}
+
+impl CsvMapping {
+    /// `--csv-mapping`: путь к JSON-файлу или строка вида "timestamp=0;price=2;amount=3;header=false;time_format=us"
+    pub fn load(spec: &str) -> Result<Self> {
+        let mapping = if Path::new(spec).is_file() {
+            let text = std::fs::read_to_string(spec)?;
+            serde_json::from_str(&text).with_context(|| format!("invalid CSV mapping file {:?}", spec))?
+        } else {
+            Self::parse_inline(spec)?
+        };
+        // Ошибки в формате времени и разделителе видны сразу, а не на первой строке файла
+        parse_delimiter(&mapping.delimiter)?;
+        TimestampFormat::parse(&mapping.time_format)?;
+        Ok(mapping)
+    }
+
+    fn parse_inline(spec: &str) -> Result<Self> {
+        let mut mapping = Self::default();
+        for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (key, value) = entry.split_once('=').with_context(|| format!("expected key=value in CSV mapping, got {:?}", entry))?;
+            let values = || value.split('|').map(|v| v.trim().to_string()).collect::<Vec<_>>();
+            match key.trim() {
+                "delimiter" => mapping.delimiter = value.to_string(),
+                "header" => mapping.header = value.trim().parse().with_context(|| format!("invalid header flag {:?}", value))?,
+                "timestamp" => mapping.timestamp = Column::parse(value),
+                "price" => mapping.price = Column::parse(value),
+                "amount" => mapping.amount = Column::parse(value),
+                "side" => mapping.side = Some(Column::parse(value)),
+                "id" => mapping.id = Some(Column::parse(value)),
+                "exchange" => mapping.exchange = Some(Column::parse(value)),
+                "base" => mapping.base = Some(Column::parse(value)),
+                "quote" => mapping.quote = Some(Column::parse(value)),
+                "time_format" => mapping.time_format = value.trim().to_string(),
+                "buy" => mapping.buy = values(),
+                "sell" => mapping.sell = values(),
+                other => bail!("unknown CSV mapping key {:?}", other),
+            }
+        }
+        Ok(mapping)
+    }
+
+    fn resolve(&self, headers: Option<&StringRecord>) -> Result<Resolved> {
+        let find = |column: &Column| -> Result<usize> {
+            match column {
+                Column::Index(idx) => Ok(*idx),
+                Column::Name(name) => headers
+                    .with_context(|| format!("column {:?} is referenced by name, but the file has no header", name))?
+                    .iter()
+                    .position(|h| h.trim().eq_ignore_ascii_case(name))
+                    .with_context(|| format!("column {:?} not found in header", name)),
+            }
+        };
+        let optional = |column: &Option<Column>| column.as_ref().map(find).transpose();
+        Ok(Resolved {
+            timestamp: find(&self.timestamp)?,
+            price: find(&self.price)?,
+            amount: find(&self.amount)?,
+            side: optional(&self.side)?,
+            id: optional(&self.id)?,
+            exchange: optional(&self.exchange)?,
+            base: optional(&self.base)?,
+            quote: optional(&self.quote)?,
+        })
+    }
+
+    fn parse_side(&self, value: &str) -> Side {
+        let value = value.trim();
+        if self.buy.iter().any(|b| b.eq_ignore_ascii_case(value)) {
+            Side::Buy
+        } else if self.sell.iter().any(|s| s.eq_ignore_ascii_case(value)) {
+            Side::Sell
+        } else {
+            Side::Unknown
+        }
+    }
+
+    /// Собирает трейд из уже извлечённых значений полей (строка CSV или объект NDJSON)
+    pub(crate) fn to_trade(&self, fields: &RawTrade, time_format: &TimestampFormat) -> Result<Trade> {
+        let number = |value: &str, name: &str| -> Result<f64> {
+            value.trim().parse::<f64>().with_context(|| format!("invalid {} {:?}", name, value))
+        };
+        let text = |value: Option<&str>| value.unwrap_or_default().trim().to_string();
+        let ts = time_format.to_millis(fields.timestamp)?;
+        Ok(Trade {
+            instrument: Instrument {
+                pair: Pair { base_id: text(fields.base), quote_id: text(fields.quote) },
+                exchange: text(fields.exchange),
+                market_type: MarketType::Spot,
+            },
+            id: fields.id.map(|id| id.trim().to_string()).unwrap_or_else(|| ts.to_string()),
+            price: number(fields.price, "price")?,
+            amount: number(fields.amount, "amount")?,
+            side: fields.side.map(|side| self.parse_side(side)).unwrap_or(Side::Unknown),
+            timestamp: Utc.timestamp_millis_opt(ts).single().with_context(|| format!("invalid time {}", ts))?,
+        })
+    }
+
+    pub fn read_trades(&self, reader: impl Read) -> Result<Vec<Trade>> {
+        let time_format = TimestampFormat::parse(&self.time_format)?;
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(parse_delimiter(&self.delimiter)?)
+            .has_headers(self.header)
+            .flexible(true)
+            .from_reader(reader);
+        let headers = if self.header { Some(rdr.headers()?.clone()) } else { None };
+        let columns = self.resolve(headers.as_ref())?;
+        let mut trades = Vec::new();
+        for result in rdr.records() {
+            let record = result?;
+            // Номер строки в файле: с учётом заголовка и многострочных полей в кавычках
+            let line = record.position().map_or(0, |p| p.line());
+            let get = |idx: usize| record.get(idx).unwrap_or_default();
+            let fields = RawTrade {
+                timestamp: get(columns.timestamp),
+                price: get(columns.price),
+                amount: get(columns.amount),
+                side: columns.side.map(get),
+                id: columns.id.map(get),
+                exchange: columns.exchange.map(get),
+                base: columns.base.map(get),
+                quote: columns.quote.map(get),
+            };
+            trades.push(self.to_trade(&fields, &time_format).with_context(|| format!("line {}", line))?);
+        }
+        Ok(trades)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_timestamp_formats() {
+        let expected = 1714000000123;
+        assert_eq!(TimestampFormat::parse("ms").unwrap().to_millis("1714000000123").unwrap(), expected);
+        assert_eq!(TimestampFormat::parse("s").unwrap().to_millis("1714000000.123").unwrap(), expected);
+        assert_eq!(TimestampFormat::parse("ns").unwrap().to_millis("1714000000123456789").unwrap(), expected);
+        let auto = TimestampFormat::parse("auto").unwrap();
+        for value in ["1714000000.123", "1714000000123", "1714000000123456", "1714000000123456789"] {
+            assert_eq!(auto.to_millis(value).unwrap(), expected, "{}", value);
+        }
+        let iso = TimestampFormat::parse("iso8601").unwrap();
+        assert_eq!(iso.to_millis("2024-04-24T23:06:40.123Z").unwrap(), expected);
+        assert_eq!(iso.to_millis("2024-04-24 23:06:40.123").unwrap(), expected);
+        let custom = TimestampFormat::parse("%d.%m.%Y %H:%M:%S%.3f").unwrap();
+        assert_eq!(custom.to_millis("24.04.2024 23:06:40.123").unwrap(), expected);
+        assert!(TimestampFormat::parse("minutes").is_err());
+        // Переполнение при переводе секунд в миллисекунды — ошибка, а не мусорное время
+        let seconds = TimestampFormat::parse("s").unwrap();
+        assert!(seconds.to_millis("9223372036854776").is_err());
+        assert!(seconds.to_millis("1e300").is_err());
+    }
+
+    #[test]
+    fn test_inline_mapping_headerless() {
+        let mapping = CsvMapping::load("delimiter=semicolon;header=false;timestamp=0;price=2;amount=3;side=1;time_format=s;buy=B;sell=S").unwrap();
+        let data = "1714000000;B;50000.5;0.1\n1714000001;S;50010;0.2\n1714000002;?;50020;0.3\n";
+        let trades = mapping.read_trades(Cursor::new(data)).unwrap();
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].price, 50000.5);
+        assert_eq!(trades[0].timestamp.timestamp_millis(), 1714000000000);
+        assert_eq!(trades[0].side, Side::Buy);
+        assert_eq!(trades[1].side, Side::Sell);
+        assert_eq!(trades[2].side, Side::Unknown);
+    }
+
+    #[test]
+    fn test_json_mapping_by_name() {
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_mapping_{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"delimiter": "tab", "timestamp": "Time", "price": "Px", "amount": 3, "side": "Dir",
+                "time_format": "iso8601", "buy": ["long"], "sell": ["short"]}"#,
+        )
+        .unwrap();
+        let mapping = CsvMapping::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).ok();
+        let mapping = mapping.unwrap();
+        let data = "Time\tPx\tDir\tQty\n2024-04-24T23:06:40Z\t50000\tLONG\t0.1\n";
+        let trades = mapping.read_trades(Cursor::new(data)).unwrap();
+        assert_eq!(trades[0].amount, 0.1);
+        assert_eq!(trades[0].side, Side::Buy);
+        assert!(CsvMapping::load("price=1;colour=red").is_err());
+        assert!(mapping.read_trades(Cursor::new("When\tPx\tDir\tQty\n")).is_err());
+        // Номер строки в ошибке считается с заголовком
+        let data = "Time\tPx\tDir\tQty\n2024-04-24T23:06:40Z\t50000\tLONG\t0.1\nyesterday\t50000\tLONG\t0.1\n";
+        let err = mapping.read_trades(Cursor::new(data)).unwrap_err();
+        assert_eq!(err.to_string(), "line 3");
+    }
+}
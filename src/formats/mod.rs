@@ -0,0 +1,86 @@
+pub mod csv;
+pub mod parquet;
+pub mod duckdb;
+pub mod questdb;
+pub mod clickhouse;
+pub mod auto;
+pub mod compression;
+pub mod binance;
+pub mod tardis;
+pub mod mapping;
+pub mod ndjson;
+pub mod ipc;
+pub mod sqlite;
+pub mod postgres;
+pub mod stdin;
+#[cfg(test)]
+pub(crate) mod mock_http;
+
+use anyhow::{bail, Context, Result};
+use candle_generator::{MarketType, Side};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+/// Общий разбор стороны сделки для всех адаптеров (buy/sell в любом регистре)
+pub fn parse_side(s: &str) -> Side {
+    match s.trim().to_lowercase().as_str() {
+        "buy" => Side::Buy,
+        "sell" => Side::Sell,
+        _ => Side::Unknown,
+    }
+}
+
+/// Общий разбор типа рынка (Spot/Futures/Margin), неизвестные значения считаются спотом
+pub fn parse_market_type(s: &str) -> MarketType {
+    match s.trim().to_lowercase().as_str() {
+        "futures" | "future" | "perpetual" | "swap" => MarketType::Futures,
+        "margin" => MarketType::Margin,
+        _ => MarketType::Spot,
+    }
+}
+
+/// Граница диапазона времени для `--from`/`--to`: миллисекунды от эпохи, RFC 3339 или YYYY-MM-DD
+pub fn parse_time_bound(s: &str) -> Result<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(ms) = s.parse::<i64>() {
+        return Utc.timestamp_millis_opt(ms).single().with_context(|| format!("invalid timestamp {}", ms));
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+    }
+    bail!("cannot parse time {:?} (expected milliseconds, RFC 3339 or YYYY-MM-DD)", s)
+}
+
+/// Разбивает полуинтервал [start, end) в миллисекундах на последовательные окна по `step` для постраничных запросов
+pub fn time_chunks(start: i64, end: i64, step: i64) -> Vec<(i64, i64)> {
+    let mut chunks = Vec::new();
+    let mut lo = start;
+    while lo < end {
+        let hi = lo.saturating_add(step.max(1)).min(end);
+        chunks.push((lo, hi));
+        lo = hi;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_bound() {
+        let expected = Utc.timestamp_millis_opt(1714003200000).unwrap();
+        assert_eq!(parse_time_bound("1714003200000").unwrap(), expected);
+        assert_eq!(parse_time_bound("2024-04-25T00:00:00Z").unwrap(), expected);
+        assert_eq!(parse_time_bound("2024-04-25").unwrap(), expected);
+        assert!(parse_time_bound("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_time_chunks() {
+        assert_eq!(time_chunks(0, 25, 10), vec![(0, 10), (10, 20), (20, 25)]);
+        assert!(time_chunks(10, 10, 5).is_empty());
+    }
+}
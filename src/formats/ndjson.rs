@@ -0,0 +1,253 @@
+use super::super::Args;
+use anyhow::{bail, Result};
+use candle_generator::Trade;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::time::Instant;
+use crate::discovery::discover_inputs;
+use crate::pipeline::Pipeline;
+use crate::progress;
+use super::compression::for_each_entry;
+use super::mapping::{Column, CsvMapping, RawTrade, TimestampFormat};
+
+const NDJSON_SUFFIXES: [&str; 2] = [".jsonl", ".ndjson"];
+const COMPRESSED_SUFFIXES: [&str; 4] = ["", ".gz", ".zst", ".bz2"];
+
+/// Раскладка по умолчанию: поля с теми же именами, что и колонки native CSV
+fn default_mapping() -> CsvMapping {
+    let name = |n: &str| Some(Column::Name(n.to_string()));
+    CsvMapping {
+        side: name("side"),
+        id: name("id"),
+        exchange: name("exchange"),
+        base: name("base"),
+        quote: name("quote"),
+        ..CsvMapping::default()
+    }
+}
+
+/// Значение поля по имени; вложенные объекты через точку ("data.p")
+fn lookup(object: &Value, column: &Column) -> Result<Option<String>> {
+    let Column::Name(name) = column else {
+        bail!("NDJSON fields must be mapped by name, got position {:?}", column);
+    };
+    let mut value = object;
+    for key in name.split('.') {
+        match value.get(key) {
+            Some(v) => value = v,
+            None => return Ok(None),
+        }
+    }
+    Ok(match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+    })
+}
+
+fn parse_line(line: &str, mapping: &CsvMapping, time_format: &TimestampFormat) -> Result<Trade> {
+    let object: Value = serde_json::from_str(line)?;
+    if !object.is_object() {
+        bail!("expected a JSON object");
+    }
+    let required = |column: &Column, name: &str| -> Result<String> {
+        match lookup(&object, column)? {
+            Some(value) => Ok(value),
+            None => bail!("missing {} field {:?}", name, column),
+        }
+    };
+    let optional = |column: &Option<Column>| column.as_ref().map(|c| lookup(&object, c)).transpose().map(Option::flatten);
+    let timestamp = required(&mapping.timestamp, "timestamp")?;
+    let price = required(&mapping.price, "price")?;
+    let amount = required(&mapping.amount, "amount")?;
+    let side = optional(&mapping.side)?;
+    let id = optional(&mapping.id)?;
+    let exchange = optional(&mapping.exchange)?;
+    let base = optional(&mapping.base)?;
+    let quote = optional(&mapping.quote)?;
+    let fields = RawTrade {
+        timestamp: &timestamp,
+        price: &price,
+        amount: &amount,
+        side: side.as_deref(),
+        id: id.as_deref(),
+        exchange: exchange.as_deref(),
+        base: base.as_deref(),
+        quote: quote.as_deref(),
+    };
+    mapping.to_trade(&fields, time_format)
+}
+
+/// Сколько битых строк одного потока сохраняется с причиной (остальные только считаются)
+const MAX_MALFORMED_SAMPLES: usize = 10;
+
+/// Номер битой строки (с единицы) и причина
+pub type MalformedLine = (usize, String);
+
+/// Битые строки потока: общее число и первые `MAX_MALFORMED_SAMPLES` с номером и причиной
+#[derive(Debug, Default)]
+pub struct MalformedLines {
+    pub count: usize,
+    pub samples: Vec<MalformedLine>,
+}
+
+/// Читает NDJSON с трейдами. Битые строки не прерывают чтение, а подсчитываются
+/// (первые — с номером строки), чтобы вызывающий мог о них сообщить.
+pub fn read_ndjson_trades(reader: impl Read, mapping: &CsvMapping) -> Result<(Vec<Trade>, MalformedLines)> {
+    let time_format = TimestampFormat::parse(&mapping.time_format)?;
+    let mut trades = Vec::new();
+    let mut malformed = MalformedLines::default();
+    for (idx, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_line(&line, mapping, &time_format) {
+            Ok(trade) => trades.push(trade),
+            Err(e) => {
+                malformed.count += 1;
+                if malformed.samples.len() < MAX_MALFORMED_SAMPLES {
+                    malformed.samples.push((idx + 1, format!("{:#}", e)));
+                }
+            }
+        }
+    }
+    Ok((trades, malformed))
+}
+
+/// Ошибка, если в потоке нет ни одного трейда при битых строках или доля битых строк больше `max_ratio`:
+/// скорее всего, неверна раскладка (`--csv-mapping`) или это не тот файл
+fn check_malformed(source: &Path, parsed: usize, malformed: &MalformedLines, max_ratio: f64) -> Result<()> {
+    if malformed.count == 0 {
+        return Ok(());
+    }
+    let first = malformed.samples.first().map(|(line, error)| format!("line {}: {}", line, error)).unwrap_or_default();
+    if parsed == 0 {
+        bail!("no valid trades in {:?}: all {} line(s) are malformed (first: {})", source, malformed.count, first);
+    }
+    let ratio = malformed.count as f64 / (parsed + malformed.count) as f64;
+    if ratio > max_ratio {
+        bail!(
+            "{:?}: {} of {} line(s) are malformed ({:.1}% > --max-malformed-ratio {}; first: {})",
+            source,
+            malformed.count,
+            parsed + malformed.count,
+            ratio * 100.0,
+            max_ratio,
+            first
+        );
+    }
+    Ok(())
+}
+
+/// NDJSON-файл, в том числе сжатый, или zip-архив с ними
+pub fn is_ndjson_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    name.ends_with(".zip")
+        || NDJSON_SUFFIXES
+            .iter()
+            .any(|suffix| COMPRESSED_SUFFIXES.iter().any(|c| name.ends_with(&format!("{}{}", suffix, c))))
+}
+
+/// Раскладка из `--csv-mapping` или по умолчанию
+pub fn ndjson_mapping(args: &Args) -> Result<CsvMapping> {
+    if !(0.0..=1.0).contains(&args.max_malformed_ratio) {
+        bail!("--max-malformed-ratio must be between 0 and 1, got {}", args.max_malformed_ratio);
+    }
+    Ok(match &args.csv_mapping {
+        Some(spec) => CsvMapping::load(spec)?,
+        None => default_mapping(),
+    })
+}
+
+/// Прогоняет через пайплайн один распакованный NDJSON-поток, сообщая о битых строках в stderr
+pub fn process_ndjson_reader(pipeline: &mut Pipeline, symbol: &str, source: &Path, reader: &mut dyn Read, mapping: &CsvMapping) -> Result<()> {
+    let io_start = Instant::now();
+    let (trades, malformed) = read_ndjson_trades(reader, mapping)?;
+    for (line, error) in &malformed.samples {
+        eprintln!("    Malformed line {} in {:?}: {}", line, source, error);
+    }
+    if malformed.count > 0 {
+        eprintln!("    Skipped {} malformed line(s) in {:?}", malformed.count, source);
+    }
+    pipeline.stats.add_malformed_lines(malformed.count);
+    check_malformed(source, trades.len(), &malformed, pipeline.args().max_malformed_ratio)?;
+    pipeline.stats.io_time += io_start.elapsed();
+    pipeline.process_trades(symbol, source, trades)
+}
+
+pub fn process_ndjson_batch(args: &Args) -> Result<()> {
+    let mapping = ndjson_mapping(args)?;
+    let mut pipeline = Pipeline::new(args)?;
+    let inputs = discover_inputs(args, is_ndjson_file)?;
+    progress!("Batch symbols: {:?}", inputs.iter().map(|(symbol, _)| symbol).collect::<Vec<_>>());
+    for (symbol, files) in &inputs {
+        pipeline.begin_symbol(symbol, files.len());
+        for file_path in files {
+            progress!("  File: {:?}", file_path.file_name().unwrap());
+            for_each_entry(file_path, &NDJSON_SUFFIXES, |source, reader| {
+                process_ndjson_reader(&mut pipeline, symbol, source, reader, &mapping)
+            })?;
+        }
+    }
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_generator::Side;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_ndjson_default_mapping() {
+        let data = "{\"timestamp\":1714000000000,\"price\":50000.0,\"amount\":0.1,\"side\":\"buy\",\"base\":\"BTC\"}\n\
+                    \n\
+                    {\"timestamp\":1714000001000,\"price\":\"50010.5\",\"amount\":0.2}\n\
+                    {\"timestamp\":1714000002000,\"price\":50020\n\
+                    [1, 2]\n\
+                    {\"timestamp\":1714000003000,\"amount\":0.3}\n";
+        let (trades, malformed) = read_ndjson_trades(Cursor::new(data), &default_mapping()).unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].side, Side::Buy);
+        assert_eq!(trades[0].instrument.pair.base_id, "BTC");
+        assert_eq!(trades[1].price, 50010.5);
+        assert_eq!(trades[1].side, Side::Unknown);
+        let lines: Vec<usize> = malformed.samples.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [4, 5, 6]);
+        assert!(malformed.samples[2].1.contains("price"));
+        // 3 битых строки из 5: файл отвергается при пороге по умолчанию и принимается при 0.6
+        let source = Path::new("trades.jsonl");
+        assert!(check_malformed(source, trades.len(), &malformed, 0.1).is_err());
+        assert!(check_malformed(source, trades.len(), &malformed, 0.6).is_ok());
+    }
+
+    #[test]
+    fn test_ndjson_malformed_lines_are_capped() {
+        let data = "not json\n".repeat(1000);
+        let (trades, malformed) = read_ndjson_trades(Cursor::new(data), &default_mapping()).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(malformed.count, 1000);
+        assert_eq!(malformed.samples.len(), MAX_MALFORMED_SAMPLES);
+        // Ни одной разобранной строки — ошибка при любом пороге
+        let err = check_malformed(Path::new("trades.jsonl"), 0, &malformed, 1.0).unwrap_err();
+        assert!(err.to_string().contains("all 1000 line(s) are malformed"));
+    }
+
+    #[test]
+    fn test_read_ndjson_exchange_native_fields() {
+        // Поток Binance: цена и объём строками, m = is_buyer_maker
+        let mapping = CsvMapping::load("timestamp=data.T;price=data.p;amount=data.q;id=data.t;side=data.m;buy=false;sell=true").unwrap();
+        let data = "{\"stream\":\"btcusdt@trade\",\"data\":{\"e\":\"trade\",\"t\":42,\"p\":\"50000.00\",\"q\":\"0.1\",\"T\":1714000000000,\"m\":true}}\n";
+        let (trades, malformed) = read_ndjson_trades(Cursor::new(data), &mapping).unwrap();
+        assert_eq!(malformed.count, 0);
+        assert_eq!(trades[0].id, "42");
+        assert_eq!(trades[0].side, Side::Sell);
+        assert_eq!(trades[0].timestamp.timestamp_millis(), 1714000000000);
+        let by_position = CsvMapping::load("timestamp=0").unwrap();
+        let (_, malformed) = read_ndjson_trades(Cursor::new(data), &by_position).unwrap();
+        assert_eq!(malformed.count, 1);
+    }
+}
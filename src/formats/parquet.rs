@@ -1,19 +1,359 @@
-// ВРЕМЕННО ОТКЛЮЧЕНО для прохождения тестов по CSV
-// Заготовка для batch-агрегации Parquet
-// use polars::prelude::*;
-// use parquet::file::reader::*;
-// use parquet::file::writer::*;
-// use super::super::Args;
-// use anyhow::Result;
-// use polars::prelude::*;
-// use candle_generator::{Trade, Instrument, Pair, MarketType, Side, Candle, Timeframe, CandleGenerator};
-// use std::fs;
-// use std::path::PathBuf;
-// use crate::aggregation;
-// use crate::stats::{ProcessingStats, print_summary};
-// pub struct ParquetTrade {}
-// pub fn read_trades_from_parquet(_path: &str) -> Vec<ParquetTrade> { vec![] }
-// pub fn write_candles_to_parquet(_path: &str, _candles: &[/*Candle*/]) {}
-// fn parse_intervals(interval_str: &str) -> Vec<Timeframe> { vec![] }
-// pub fn process_parquet_batch(args: &Args) -> Result<()> { Ok(()) }
-// pub fn write_candles_parquet<P: AsRef<std::path::Path>>(candles: &[Candle], out_path: P) -> Result<()> { Ok(()) } 
\ No newline at end of file
+use super::super::Args;
+use anyhow::{bail, Context, Result};
+use arrow::array::{Array, ArrayRef, AsArray, Float64Array, StringArray, TimestampMillisecondArray, UInt64Array};
+use arrow::record_batch::RecordBatch;
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Float64Type, Int64Type, Schema, TimeUnit};
+use candle_generator::{Candle, Trade, Instrument, Pair};
+use chrono::TimeZone;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::discovery::discover_inputs;
+use crate::pipeline::{Pipeline, has_extension};
+use crate::progress;
+use super::{parse_market_type, parse_side};
+
+const BATCH_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Timestamp,
+    Float,
+    Text,
+}
+
+/// Схема трейдов из README: timestamp (i64, мс), exchange, base_id, quote_id,
+/// market_type, id, price, amount, side
+const TRADE_COLUMNS: [(&str, ColumnKind); 9] = [
+    ("timestamp", ColumnKind::Timestamp),
+    ("exchange", ColumnKind::Text),
+    ("base_id", ColumnKind::Text),
+    ("quote_id", ColumnKind::Text),
+    ("market_type", ColumnKind::Text),
+    ("id", ColumnKind::Text),
+    ("price", ColumnKind::Float),
+    ("amount", ColumnKind::Float),
+    ("side", ColumnKind::Text),
+];
+
+fn accepts(kind: ColumnKind, data_type: &DataType) -> bool {
+    match kind {
+        ColumnKind::Timestamp => matches!(data_type, DataType::Int64 | DataType::Timestamp(_, _)),
+        ColumnKind::Float => matches!(data_type, DataType::Float64 | DataType::Float32),
+        ColumnKind::Text => match data_type {
+            DataType::Utf8 | DataType::LargeUtf8 => true,
+            DataType::Dictionary(_, value) => matches!(value.as_ref(), DataType::Utf8 | DataType::LargeUtf8),
+            _ => false,
+        },
+    }
+}
+
+/// Проверяет, что в схеме есть все колонки трейда нужных типов
+pub fn check_trade_schema(schema: &Schema) -> Result<()> {
+    for (name, kind) in TRADE_COLUMNS {
+        let field = schema
+            .field_with_name(name)
+            .map_err(|_| anyhow::anyhow!("missing column {:?} (expected columns: {})", name, TRADE_COLUMNS.map(|c| c.0).join(", ")))?;
+        if !accepts(kind, field.data_type()) {
+            bail!("column {:?} has type {}, expected {:?}", name, field.data_type(), kind);
+        }
+    }
+    Ok(())
+}
+
+/// Приводит колонку к типу, с которым работает конвертер: Int64 (мс), Float64 или Utf8
+fn normalized_column(batch: &RecordBatch, name: &str, kind: ColumnKind) -> Result<ArrayRef> {
+    let column = batch.column_by_name(name).with_context(|| format!("missing column {:?}", name))?;
+    let normalized = match (kind, column.data_type()) {
+        (ColumnKind::Timestamp, DataType::Timestamp(_, _)) => {
+            let millis = cast(column, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+            cast(&millis, &DataType::Int64)?
+        }
+        (ColumnKind::Timestamp, _) => cast(column, &DataType::Int64)?,
+        (ColumnKind::Float, _) => cast(column, &DataType::Float64)?,
+        (ColumnKind::Text, _) => cast(column, &DataType::Utf8)?,
+    };
+    Ok(normalized)
+}
+
+/// Конвертирует record batch со схемой трейдов в `Trade`
+pub fn trades_from_batch(batch: &RecordBatch, trades: &mut Vec<Trade>) -> Result<()> {
+    let columns = TRADE_COLUMNS
+        .iter()
+        .map(|(name, kind)| normalized_column(batch, name, *kind))
+        .collect::<Result<Vec<_>>>()?;
+    let timestamp = columns[0].as_primitive::<Int64Type>();
+    let exchange = columns[1].as_string::<i32>();
+    let base_id = columns[2].as_string::<i32>();
+    let quote_id = columns[3].as_string::<i32>();
+    let market_type = columns[4].as_string::<i32>();
+    let id = columns[5].as_string::<i32>();
+    let price = columns[6].as_primitive::<Float64Type>();
+    let amount = columns[7].as_primitive::<Float64Type>();
+    let side = columns[8].as_string::<i32>();
+    for (name, column) in [("timestamp", &columns[0]), ("price", &columns[6]), ("amount", &columns[7])] {
+        if column.null_count() > 0 {
+            bail!("column {:?} contains {} null values", name, column.null_count());
+        }
+    }
+    let text = |array: &arrow::array::StringArray, row: usize| -> String {
+        if array.is_null(row) { String::new() } else { array.value(row).to_string() }
+    };
+    trades.reserve(batch.num_rows());
+    for row in 0..batch.num_rows() {
+        let ts = timestamp.value(row);
+        trades.push(Trade {
+            instrument: Instrument {
+                pair: Pair {
+                    base_id: text(base_id, row),
+                    quote_id: text(quote_id, row),
+                },
+                exchange: text(exchange, row),
+                market_type: parse_market_type(&text(market_type, row)),
+            },
+            id: text(id, row),
+            price: price.value(row),
+            amount: amount.value(row),
+            side: parse_side(&text(side, row)),
+            timestamp: chrono::Utc
+                .timestamp_millis_opt(ts)
+                .single()
+                .with_context(|| format!("invalid timestamp {} at row {}", ts, row))?,
+        });
+    }
+    Ok(())
+}
+
+/// Читает трейды из Parquet потоково, по record batch'ам внутри row group'ов
+pub fn read_trades_from_parquet(path: &Path) -> Result<Vec<Trade>> {
+    let file = File::open(path).with_context(|| format!("cannot open {:?}", path))?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file)
+        .with_context(|| format!("{:?} is not a valid Parquet file", path))?;
+    check_trade_schema(builder.schema()).with_context(|| format!("unsupported schema in {:?}", path))?;
+    let reader = builder.with_batch_size(BATCH_SIZE).build()?;
+    let mut trades = Vec::new();
+    for batch in reader {
+        trades_from_batch(&batch?, &mut trades).with_context(|| format!("cannot read trades from {:?}", path))?;
+    }
+    Ok(trades)
+}
+
+#[derive(Debug, Clone)]
+pub struct ParquetWriteOptions {
+    pub compression: Compression,
+    pub row_group_size: usize,
+}
+
+/// Разбирает кодек сжатия: snappy, zstd[:level], gzip[:level], lz4, none
+pub fn parse_compression(s: &str) -> Result<Compression> {
+    let (codec, level) = match s.split_once(':') {
+        Some((codec, level)) => (codec, Some(level.parse::<i32>().with_context(|| format!("invalid compression level {:?}", level))?)),
+        None => (s, None),
+    };
+    Ok(match codec.to_lowercase().as_str() {
+        "snappy" => Compression::SNAPPY,
+        "zstd" => Compression::ZSTD(level.map(ZstdLevel::try_new).transpose()?.unwrap_or_default()),
+        "gzip" => Compression::GZIP(level.map(|l| GzipLevel::try_new(l as u32)).transpose()?.unwrap_or_default()),
+        "lz4" => Compression::LZ4_RAW,
+        "none" | "uncompressed" => Compression::UNCOMPRESSED,
+        other => bail!("unknown Parquet compression {:?} (expected snappy, zstd, gzip, lz4 or none)", other),
+    })
+}
+
+/// Колонки свечи в Parquet/Arrow. Кастомные метрики `custom` разворачиваются
+/// в отдельные колонки `custom_<name>` (null, если у свечи нет такой метрики).
+pub fn candle_schema(custom_keys: &BTreeSet<String>) -> Schema {
+    let mut fields = vec![
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("market_type", DataType::Utf8, false),
+        Field::new("base_id", DataType::Utf8, false),
+        Field::new("quote_id", DataType::Utf8, false),
+        Field::new("interval", DataType::Utf8, false),
+        Field::new("timestamp", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false),
+        Field::new("open", DataType::Float64, false),
+        Field::new("high", DataType::Float64, false),
+        Field::new("low", DataType::Float64, false),
+        Field::new("close", DataType::Float64, false),
+        Field::new("volume", DataType::Float64, false),
+        Field::new("trade_count", DataType::UInt64, false),
+        Field::new("volume_usdt", DataType::Float64, true),
+    ];
+    fields.extend(custom_keys.iter().map(|k| Field::new(format!("custom_{}", k), DataType::Float64, true)));
+    Schema::new(fields)
+}
+
+pub fn candles_to_record_batch(candles: &[Candle]) -> Result<RecordBatch> {
+    let custom_keys: BTreeSet<String> = candles.iter().flat_map(|c| c.custom.keys().cloned()).collect();
+    let schema = Arc::new(candle_schema(&custom_keys));
+    let text = |f: &dyn Fn(&Candle) -> String| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(candles.iter().map(f)))
+    };
+    let float = |f: &dyn Fn(&Candle) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(f)))
+    };
+    let mut columns: Vec<ArrayRef> = vec![
+        text(&|c| c.instrument.exchange.clone()),
+        text(&|c| format!("{:?}", c.instrument.market_type)),
+        text(&|c| c.instrument.pair.base_id.clone()),
+        text(&|c| c.instrument.pair.quote_id.clone()),
+        text(&|c| format!("{:?}", c.interval)),
+        Arc::new(TimestampMillisecondArray::from_iter_values(candles.iter().map(|c| c.timestamp.timestamp_millis())).with_timezone("UTC")),
+        float(&|c| c.open),
+        float(&|c| c.high),
+        float(&|c| c.low),
+        float(&|c| c.close),
+        float(&|c| c.volume),
+        Arc::new(UInt64Array::from_iter_values(candles.iter().map(|c| c.trade_count as u64))),
+        Arc::new(candles.iter().map(|c| c.volume_usdt).collect::<Float64Array>()),
+    ];
+    for key in &custom_keys {
+        columns.push(Arc::new(candles.iter().map(|c| c.custom.get(key).copied()).collect::<Float64Array>()));
+    }
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+pub fn write_candles_parquet<P: AsRef<Path>>(candles: &[Candle], out_path: P, options: &ParquetWriteOptions) -> Result<()> {
+    write_candles_parquet_to(candles, File::create(out_path)?, options)
+}
+
+/// Пишет свечи в Parquet в произвольный поток (например, stdout)
+pub fn write_candles_parquet_to(candles: &[Candle], out: impl Write + Send, options: &ParquetWriteOptions) -> Result<()> {
+    let batch = candles_to_record_batch(candles)?;
+    let props = WriterProperties::builder()
+        .set_compression(options.compression)
+        .set_max_row_group_size(options.row_group_size.max(1))
+        .build();
+    let mut writer = ArrowWriter::try_new(out, batch.schema(), Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+pub fn process_parquet_batch(args: &Args) -> Result<()> {
+    let mut pipeline = Pipeline::new(args)?;
+    let inputs = discover_inputs(args, |p| has_extension(p, &["parquet"]))?;
+    progress!("Batch symbols: {:?}", inputs.iter().map(|(symbol, _)| symbol).collect::<Vec<_>>());
+    for (symbol, files) in &inputs {
+        pipeline.begin_symbol(symbol, files.len());
+        for file_path in files {
+            let io_start = Instant::now();
+            progress!("  File: {:?}", file_path.file_name().unwrap());
+            let trades = read_trades_from_parquet(file_path)?;
+            pipeline.stats.io_time += io_start.elapsed();
+            pipeline.process_trades(symbol, file_path, trades)?;
+        }
+    }
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Int64Array;
+    use std::path::PathBuf;
+
+    fn write_parquet(name: &str, columns: Vec<(&str, ArrayRef)>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_{}_{}.parquet", name, std::process::id()));
+        let fields: Vec<Field> = columns.iter().map(|(n, a)| Field::new(*n, a.data_type().clone(), true)).collect();
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), columns.into_iter().map(|(_, a)| a).collect()).unwrap();
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    fn trade_columns(price: ArrayRef) -> Vec<(&'static str, ArrayRef)> {
+        let text = |v: &[&str]| -> ArrayRef { Arc::new(StringArray::from(v.to_vec())) };
+        vec![
+            ("timestamp", Arc::new(Int64Array::from(vec![1714000000000, 1714000060000]))),
+            ("exchange", text(&["binance", "binance"])),
+            ("base_id", text(&["BTC", "BTC"])),
+            ("quote_id", text(&["USDT", "USDT"])),
+            ("market_type", text(&["Spot", "Futures"])),
+            ("id", text(&["t1", "t2"])),
+            ("price", price),
+            ("amount", Arc::new(Float64Array::from(vec![0.1, 0.2]))),
+            ("side", text(&["Buy", "Sell"])),
+        ]
+    }
+
+    #[test]
+    fn test_read_trades_from_parquet() {
+        let path = write_parquet("ok", trade_columns(Arc::new(Float64Array::from(vec![50000.0, 50100.0]))));
+        let trades = read_trades_from_parquet(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, 50000.0);
+        assert_eq!(trades[0].instrument.pair.base_id, "BTC");
+        assert_eq!(trades[0].side, candle_generator::Side::Buy);
+        assert_eq!(trades[1].instrument.market_type, candle_generator::MarketType::Futures);
+        assert_eq!(trades[1].timestamp.timestamp_millis(), 1714000060000);
+    }
+
+    #[test]
+    fn test_write_candles_parquet() {
+        use crate::fixtures::sample_candle;
+        use candle_generator::Timeframe;
+        use std::collections::HashMap;
+        let candle = |ts: i64, custom: HashMap<String, f64>| Candle {
+            open: 50000.0,
+            high: 50100.0,
+            low: 49900.0,
+            custom,
+            ..sample_candle(Timeframe::m1, ts, 50050.0)
+        };
+        let candles = vec![
+            candle(1714000020000, HashMap::from([("vwap".to_string(), 50010.0)])),
+            candle(1714000080000, HashMap::new()),
+        ];
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_candles_{}.parquet", std::process::id()));
+        let options = ParquetWriteOptions { compression: parse_compression("zstd:3").unwrap(), row_group_size: 1 };
+        write_candles_parquet(&candles, &path, &options).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        let batches: Vec<RecordBatch> = builder.build().unwrap().collect::<Result<_, _>>().unwrap();
+        std::fs::remove_file(&path).ok();
+        let batch = arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.column_by_name("interval").unwrap().as_string::<i32>().value(0), "m1");
+        assert_eq!(batch.column_by_name("trade_count").unwrap().as_primitive::<arrow::datatypes::UInt64Type>().value(1), 3);
+        let vwap = batch.column_by_name("custom_vwap").unwrap().as_primitive::<Float64Type>();
+        assert_eq!(vwap.value(0), 50010.0);
+        assert!(vwap.is_null(1));
+        assert!(batch.column_by_name("volume_usdt").unwrap().is_null(0));
+    }
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!(parse_compression("snappy").unwrap(), Compression::SNAPPY);
+        assert_eq!(parse_compression("none").unwrap(), Compression::UNCOMPRESSED);
+        assert!(parse_compression("brotli-ish").is_err());
+    }
+
+    #[test]
+    fn test_parquet_missing_column() {
+        let mut columns = trade_columns(Arc::new(Float64Array::from(vec![50000.0, 50100.0])));
+        columns.retain(|(name, _)| *name != "side");
+        let path = write_parquet("missing", columns);
+        let err = read_trades_from_parquet(&path).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert!(format!("{:#}", err).contains("missing column \"side\""));
+    }
+
+    #[test]
+    fn test_parquet_wrong_type() {
+        let path = write_parquet("wrong_type", trade_columns(Arc::new(StringArray::from(vec!["50000", "50100"]))));
+        let err = read_trades_from_parquet(&path).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert!(format!("{:#}", err).contains("column \"price\" has type Utf8"));
+    }
+}
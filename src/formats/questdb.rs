@@ -1,17 +1,423 @@
-// ВРЕМЕННО ОТКЛЮЧЕНО для прохождения тестов по CSV
-// Заготовка для batch-агрегации QuestDB
-// use polars::prelude::*;
-// use questdb::Client;
-// use super::super::Args;
-// use anyhow::Result;
-// use candle_generator::{Trade, Instrument, Pair, MarketType, Side, Candle, Timeframe, CandleGenerator};
-// use std::fs;
-// use std::path::PathBuf;
-// use crate::aggregation;
-// use crate::stats::{ProcessingStats, print_summary};
-// use csv::ReaderBuilder;
-// pub struct QuestdbTrade {}
-// pub fn read_trades_from_questdb(_conn_str: &str) -> Vec<QuestdbTrade> { vec![] }
-// pub fn write_candles_to_questdb(_conn_str: &str, _candles: &[/*Candle*/]) {}
-// fn parse_intervals(interval_str: &str) -> Vec<Timeframe> { vec![] }
-// pub fn process_questdb_batch(args: &Args) -> Result<()> { Ok(()) } 
\ No newline at end of file
+use super::super::Args;
+use anyhow::{bail, Context, Result};
+use candle_generator::{Candle, Trade, Instrument, Pair, MarketType};
+use chrono::TimeZone;
+use csv::ReaderBuilder;
+use serde::Deserialize;
+use std::io::{BufWriter, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use crate::pipeline::{Pipeline, resolve_symbols};
+use crate::progress;
+use super::{parse_side, parse_time_bound, time_chunks};
+
+/// Строка выгрузки трейдов: время уже переведено в миллисекунды в самом запросе
+#[derive(Debug, Deserialize)]
+struct QuestdbTrade {
+    ts: i64,
+    price: f64,
+    amount: f64,
+    side: Option<String>,
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Трейды в QuestDB, читаемые через HTTP-эндпоинт /exp (CSV)
+pub struct QuestdbSource {
+    url: String,
+    table: String,
+    symbol_column: String,
+    timestamp_column: String,
+    client: reqwest::blocking::Client,
+}
+
+impl QuestdbSource {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        Ok(Self {
+            url: args.questdb_url.trim_end_matches('/').to_string(),
+            table: args.questdb_table.clone(),
+            symbol_column: args.questdb_symbol_column.clone(),
+            timestamp_column: args.questdb_timestamp_column.clone(),
+            client: reqwest::blocking::Client::builder().timeout(Duration::from_secs(300)).build()?,
+        })
+    }
+
+    /// Выполняет запрос и возвращает CSV-ответ с заголовком
+    fn query(&self, sql: &str) -> Result<csv::Reader<reqwest::blocking::Response>> {
+        let response = self
+            .client
+            .get(format!("{}/exp", self.url))
+            .query(&[("query", sql)])
+            .send()
+            .with_context(|| format!("cannot reach QuestDB at {}", self.url))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().unwrap_or_default();
+            bail!("QuestDB query failed ({}): {}\n  query: {}", status, body.trim(), sql);
+        }
+        Ok(ReaderBuilder::new().has_headers(true).from_reader(response))
+    }
+
+    /// Все пары в таблице (для `--symbol ALL`)
+    pub fn symbols(&self) -> Result<Vec<String>> {
+        let sql = format!(
+            "SELECT DISTINCT {} FROM {} ORDER BY 1",
+            quote_ident(&self.symbol_column),
+            quote_ident(&self.table)
+        );
+        let mut rdr = self.query(&sql)?;
+        let mut symbols = Vec::new();
+        for record in rdr.records() {
+            let record = record?;
+            if let Some(symbol) = record.get(0).filter(|s| !s.is_empty()) {
+                symbols.push(symbol.to_string());
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Диапазон времени трейдов пары в миллисекундах [min, max + 1), None если трейдов нет
+    pub fn bounds(&self, symbol: &str) -> Result<Option<(i64, i64)>> {
+        let ts = quote_ident(&self.timestamp_column);
+        let sql = format!(
+            "SELECT cast(min({ts}) AS long) / 1000, cast(max({ts}) AS long) / 1000 FROM {} WHERE {} = {}",
+            quote_ident(&self.table),
+            quote_ident(&self.symbol_column),
+            quote_literal(symbol),
+            ts = ts
+        );
+        let mut rdr = self.query(&sql)?;
+        let Some(record) = rdr.records().next().transpose()? else { return Ok(None) };
+        let lo = record.get(0).and_then(|v| v.parse::<i64>().ok());
+        let hi = record.get(1).and_then(|v| v.parse::<i64>().ok());
+        Ok(lo.zip(hi).map(|(lo, hi)| (lo, hi + 1)))
+    }
+
+    /// Трейды пары за полуинтервал [from, to) в миллисекундах, по возрастанию времени
+    pub fn read_trades(&self, symbol: &str, from: i64, to: i64) -> Result<Vec<Trade>> {
+        let ts = quote_ident(&self.timestamp_column);
+        let sql = format!(
+            "SELECT cast({ts} AS long) / 1000 ts, price, amount, side FROM {} \
+             WHERE {} = {} AND {ts} >= cast({} AS timestamp) AND {ts} < cast({} AS timestamp) ORDER BY {ts}",
+            quote_ident(&self.table),
+            quote_ident(&self.symbol_column),
+            quote_literal(symbol),
+            from * 1000,
+            to * 1000,
+            ts = ts
+        );
+        let mut rdr = self.query(&sql)?;
+        let mut trades = Vec::new();
+        for result in rdr.deserialize() {
+            let row: QuestdbTrade = result.with_context(|| format!("unexpected QuestDB response for {}", symbol))?;
+            trades.push(Trade {
+                // В таблице только символ пары, разбивка на base/quote неизвестна
+                instrument: Instrument {
+                    pair: Pair {
+                        base_id: symbol.to_string(),
+                        quote_id: String::new(),
+                    },
+                    exchange: String::new(),
+                    market_type: MarketType::Spot,
+                },
+                id: row.ts.to_string(),
+                price: row.price,
+                amount: row.amount,
+                side: parse_side(row.side.as_deref().unwrap_or_default()),
+                timestamp: chrono::Utc
+                    .timestamp_millis_opt(row.ts)
+                    .single()
+                    .with_context(|| format!("invalid timestamp {} for {}", row.ts, symbol))?,
+            });
+        }
+        Ok(trades)
+    }
+}
+
+/// Куда писать свечи по InfluxDB Line Protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum IlpTransport {
+    /// Сырой ILP поверх TCP (порт 9009), сервер не подтверждает запись и при ошибке молча рвёт соединение
+    Tcp(String),
+    /// ILP через HTTP `/write` (порт 9000), ошибки возвращаются в ответе
+    Http(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct IlpOptions {
+    pub transport: IlpTransport,
+    pub table: String,
+    pub batch_size: usize,
+}
+
+impl IlpOptions {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let endpoint = args.questdb_ilp.trim_end_matches('/');
+        let transport = if let Some(addr) = endpoint.strip_prefix("tcp://") {
+            IlpTransport::Tcp(addr.to_string())
+        } else if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+            IlpTransport::Http(endpoint.to_string())
+        } else {
+            bail!("unsupported ILP endpoint {:?} (expected tcp://host:port or http://host:port)", args.questdb_ilp);
+        };
+        if args.ilp_batch_size == 0 {
+            bail!("--ilp-batch-size must be positive");
+        }
+        Ok(Self {
+            transport,
+            table: args.questdb_candles_table.clone(),
+            batch_size: args.ilp_batch_size,
+        })
+    }
+}
+
+/// Экранирование имён и значений тегов ILP: запятая, пробел и знак равенства
+fn ilp_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace(' ', "\\ ").replace('=', "\\=")
+}
+
+/// Строка ILP для свечи: exchange, symbol, market_type и interval — теги (SYMBOL-колонки в QuestDB),
+/// OHLCV — поля, время свечи — designated timestamp в наносекундах
+pub fn candle_to_ilp(table: &str, symbol: &str, candle: &Candle) -> String {
+    let mut line = ilp_escape(table);
+    let tags = [
+        ("exchange", candle.instrument.exchange.clone()),
+        ("symbol", symbol.to_string()),
+        ("market_type", format!("{:?}", candle.instrument.market_type)),
+        ("interval", format!("{:?}", candle.interval)),
+    ];
+    // Пустые значения тегов ILP не допускает
+    for (name, value) in tags.iter().filter(|(_, v)| !v.is_empty()) {
+        line.push_str(&format!(",{}={}", name, ilp_escape(value)));
+    }
+    let mut fields = vec![
+        format!("open={}", candle.open),
+        format!("high={}", candle.high),
+        format!("low={}", candle.low),
+        format!("close={}", candle.close),
+        format!("volume={}", candle.volume),
+        format!("trade_count={}i", candle.trade_count),
+    ];
+    if let Some(volume_usdt) = candle.volume_usdt {
+        fields.push(format!("volume_usdt={}", volume_usdt));
+    }
+    let mut custom: Vec<_> = candle.custom.iter().collect();
+    custom.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, value) in custom {
+        fields.push(format!("{}={}", ilp_escape(&format!("custom_{}", name)), value));
+    }
+    line.push(' ');
+    line.push_str(&fields.join(","));
+    line.push_str(&format!(" {}\n", candle.timestamp.timestamp_millis() * 1_000_000));
+    line
+}
+
+/// Свечи в QuestDB по ILP. TCP-соединение открывается при первой записи и держится до конца прогона.
+/// Сырой ILP/TCP не подтверждает запись: строку, которую сервер отверг, он молча отбрасывает и закрывает
+/// соединение, а ошибка видна разве что на одной из следующих записей. Для надёжной доставки — HTTP `/write`:
+/// каждая пачка подтверждается статусом ответа.
+pub struct QuestdbSink {
+    opts: IlpOptions,
+    stream: Option<BufWriter<TcpStream>>,
+    client: reqwest::blocking::Client,
+}
+
+impl QuestdbSink {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        Ok(Self {
+            opts: IlpOptions::from_args(args)?,
+            stream: None,
+            client: reqwest::blocking::Client::new(),
+        })
+    }
+
+    pub fn table(&self) -> &str {
+        &self.opts.table
+    }
+
+    fn stream(&mut self, addr: &str) -> Result<&mut BufWriter<TcpStream>> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect(addr).with_context(|| format!("cannot connect to QuestDB ILP at {}", addr))?;
+            self.stream = Some(BufWriter::new(stream));
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    /// Отправляет свечи пачками по `batch_size` строк
+    pub fn write_candles(&mut self, candles: &[Candle], symbol: &str) -> Result<()> {
+        if candles.is_empty() {
+            return Ok(());
+        }
+        let table = self.opts.table.clone();
+        let batch_size = self.opts.batch_size;
+        match self.opts.transport.clone() {
+            IlpTransport::Tcp(addr) => {
+                let writer = self.stream(&addr)?;
+                for batch in candles.chunks(batch_size) {
+                    for candle in batch {
+                        writer.write_all(candle_to_ilp(&table, symbol, candle).as_bytes())?;
+                    }
+                    writer.flush().with_context(|| format!("QuestDB ILP at {} closed the connection", addr))?;
+                }
+            }
+            IlpTransport::Http(url) => {
+                for batch in candles.chunks(batch_size) {
+                    let body: String = batch.iter().map(|c| candle_to_ilp(&table, symbol, c)).collect();
+                    let response = self
+                        .client
+                        .post(format!("{}/write", url))
+                        .body(body)
+                        .send()
+                        .with_context(|| format!("cannot reach QuestDB at {}", url))?;
+                    if !response.status().is_success() {
+                        let status = response.status();
+                        bail!("QuestDB ILP write failed ({}): {}", status, response.text().unwrap_or_default().trim());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn process_questdb_batch(args: &Args) -> Result<()> {
+    if args.chunk_hours == 0 {
+        bail!("--chunk-hours must be positive");
+    }
+    let mut pipeline = Pipeline::new(args)?;
+    let source = QuestdbSource::from_args(args)?;
+    let symbols = if args.symbol()?.to_uppercase() == "ALL" { source.symbols()? } else { resolve_symbols(args)? };
+    progress!("Batch symbols: {:?}", symbols);
+    let from = args.from.as_deref().map(parse_time_bound).transpose()?.map(|t| t.timestamp_millis());
+    let to = args.to.as_deref().map(parse_time_bound).transpose()?.map(|t| t.timestamp_millis());
+    let step = args.chunk_hours as i64 * 3_600_000;
+    for symbol in &symbols {
+        let (start, end) = match (from, to) {
+            (Some(start), Some(end)) => (start, end),
+            _ => match source.bounds(symbol)? {
+                Some((lo, hi)) => (from.unwrap_or(lo), to.unwrap_or(hi)),
+                None => {
+                    progress!("\nNo trades for {} in {:?}", symbol, args.questdb_table);
+                    continue;
+                }
+            },
+        };
+        let chunks = time_chunks(start, end, step);
+        pipeline.begin_symbol(symbol, chunks.len());
+        let io_start = Instant::now();
+        let mut trades = Vec::new();
+        for (lo, hi) in chunks {
+            let chunk = source.read_trades(symbol, lo, hi)?;
+            if args.progress {
+                progress!("  Chunk: {} .. {} ({} trades)", lo, hi, chunk.len());
+            }
+            trades.extend(chunk);
+        }
+        pipeline.stats.io_time += io_start.elapsed();
+        pipeline.process_trades(symbol, Path::new(&args.questdb_table), trades)?;
+    }
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample_candle;
+    use candle_generator::Timeframe;
+    use crate::formats::mock_http::mock_server;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn mock_args(url: String) -> Args {
+        Args { questdb_url: url, ..Default::default() }
+    }
+
+    #[test]
+    fn test_read_trades_from_questdb_mock() {
+        let csv = "\"ts\",\"price\",\"amount\",\"side\"\n1714000000000,50000.0,0.1,buy\n1714000060000,50100.0,0.2,sell\n";
+        let (url, server) = mock_server(vec![(200, csv.to_string())]);
+        let source = QuestdbSource::from_args(&mock_args(url)).unwrap();
+        let trades = source.read_trades("BTCUSDT", 1714000000000, 1714003600000).unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].price, 50100.0);
+        assert_eq!(trades[1].side, candle_generator::Side::Sell);
+        assert_eq!(trades[0].timestamp.timestamp_millis(), 1714000000000);
+        assert!(requests[0].0.starts_with("GET /exp?query="));
+        assert!(requests[0].0.contains("BTCUSDT"));
+        assert!(requests[0].0.contains("1714000000000000"));
+    }
+
+    #[test]
+    fn test_questdb_bounds_and_errors() {
+        let (url, server) = mock_server(vec![
+            (200, "\"min\",\"max\"\n1714000000000,1714000060000\n".to_string()),
+            (200, "\"min\",\"max\"\n,\n".to_string()),
+            (400, "{\"error\":\"table does not exist [table=trades]\"}".to_string()),
+        ]);
+        let source = QuestdbSource::from_args(&mock_args(url)).unwrap();
+        assert_eq!(source.bounds("BTCUSDT").unwrap(), Some((1714000000000, 1714000060001)));
+        assert_eq!(source.bounds("ETHUSDT").unwrap(), None);
+        let err = source.read_trades("BTCUSDT", 0, 1).unwrap_err();
+        assert!(err.to_string().contains("table does not exist"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_candle_to_ilp() {
+        let mut candle = sample_candle(Timeframe::m1, 1714000020000, 50000.5);
+        candle.instrument.exchange = "my exchange".to_string();
+        candle.volume_usdt = Some(75000.75);
+        let line = candle_to_ilp("candles", "BTCUSDT", &candle);
+        assert_eq!(
+            line,
+            "candles,exchange=my\\ exchange,symbol=BTCUSDT,market_type=Spot,interval=m1 \
+             open=50000.5,high=50000.5,low=50000.5,close=50000.5,volume=1.5,trade_count=3i,volume_usdt=75000.75 \
+             1714000020000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_write_candles_to_questdb_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Сервер принимает ровно одно соединение: все записи прогона идут через него
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+        let args = Args { questdb_ilp: format!("tcp://{}", addr), ilp_batch_size: 2, ..Default::default() };
+        let mut sink = QuestdbSink::from_args(&args).unwrap();
+        let candles: Vec<_> = (0..5).map(|i| sample_candle(Timeframe::m1, 1714000020000 + i * 60_000, 50000.0)).collect();
+        sink.write_candles(&candles, "BTCUSDT").unwrap();
+        sink.write_candles(&candles[..2], "ETHUSDT").unwrap();
+        drop(sink);
+        let received = server.join().unwrap();
+        assert_eq!(received.lines().count(), 7);
+        assert_eq!(received.lines().filter(|l| l.starts_with("candles,exchange=binance,symbol=BTCUSDT")).count(), 5);
+    }
+
+    #[test]
+    fn test_write_candles_to_questdb_http_batches() {
+        let (url, server) = mock_server(vec![(204, String::new()), (204, String::new())]);
+        let args = Args { questdb_ilp: url, ilp_batch_size: 3, ..Default::default() };
+        let mut sink = QuestdbSink::from_args(&args).unwrap();
+        let candles: Vec<_> = (0..5).map(|i| sample_candle(Timeframe::m1, 1714000020000 + i * 60_000, 50000.0)).collect();
+        sink.write_candles(&candles, "BTCUSDT").unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].0.starts_with("POST /write"));
+        assert_eq!(requests[0].1.lines().count(), 3);
+        assert_eq!(requests[1].1.lines().count(), 2);
+        assert!(IlpOptions::from_args(&Args { questdb_ilp: "udp://localhost:9009".to_string(), ..Default::default() }).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use super::super::Args;
+use anyhow::{bail, Context, Result};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use candle_generator::{Candle, Trade, Instrument, Pair};
+use chrono::TimeZone;
+use std::path::Path;
+use std::time::Instant;
+use crate::discovery::discover_inputs;
+use crate::pipeline::{Pipeline, has_extension};
+use crate::progress;
+use super::mapping::TimestampFormat;
+use super::{parse_market_type, parse_side};
+
+/// Расширения файлов баз SQLite
+pub const SQLITE_EXTENSIONS: [&str; 3] = ["sqlite", "sqlite3", "db"];
+
+/// Заголовок любой базы SQLite 3
+pub fn is_sqlite(magic: &[u8]) -> bool {
+    magic.starts_with(b"SQLite format 3\0")
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// SQLite хранит значения без строгих типов: число, текст или NULL
+fn text_value(value: ValueRef) -> Option<String> {
+    match value {
+        ValueRef::Null | ValueRef::Blob(_) => None,
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) => Some(f.to_string()),
+        ValueRef::Text(t) => Some(String::from_utf8_lossy(t).to_string()),
+    }
+}
+
+fn float_value(value: ValueRef, name: &str) -> Result<f64> {
+    match value {
+        ValueRef::Integer(i) => Ok(i as f64),
+        ValueRef::Real(f) => Ok(f),
+        ValueRef::Text(t) => {
+            let text = String::from_utf8_lossy(t);
+            text.trim().parse().with_context(|| format!("invalid {} {:?}", name, text))
+        }
+        ValueRef::Null | ValueRef::Blob(_) => bail!("{} is NULL or BLOB", name),
+    }
+}
+
+/// Время — целое число миллисекунд или текст ISO 8601 (как пишет `datetime()`)
+fn timestamp_value(value: ValueRef) -> Result<i64> {
+    match value {
+        ValueRef::Integer(i) => Ok(i),
+        ValueRef::Real(f) => Ok(f as i64),
+        ValueRef::Text(t) => TimestampFormat::Iso8601.to_millis(&String::from_utf8_lossy(t)),
+        ValueRef::Null | ValueRef::Blob(_) => bail!("timestamp is NULL or BLOB"),
+    }
+}
+
+/// Читает трейды из базы SQLite: `SELECT *` из таблицы `table` или произвольный запрос `query`.
+/// Колонки ищутся по именам, как в таблице трейдов DuckDB; обязательны timestamp, price и amount.
+pub fn read_trades_from_sqlite(path: &Path, table: &str, query: Option<&str>) -> Result<Vec<Trade>> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("cannot open SQLite database {:?}", path))?;
+    let sql = match query {
+        Some(query) => query.to_string(),
+        None => format!("SELECT * FROM {}", quote_ident(table)),
+    };
+    let mut stmt = conn.prepare(&sql).with_context(|| format!("invalid query in {:?}: {}", path, sql))?;
+    let names: Vec<String> = stmt.column_names().iter().map(|n| n.to_lowercase()).collect();
+    let column = |name: &str| names.iter().position(|n| n == name);
+    let required = |name: &str| column(name).with_context(|| format!("query result has no column {:?}: {}", name, sql));
+    let (ts_col, price_col, amount_col) = (required("timestamp")?, required("price")?, required("amount")?);
+    let optional = ["exchange", "base_id", "quote_id", "market_type", "id", "side"].map(column);
+    let mut rows = stmt.query([])?;
+    let mut trades = Vec::new();
+    while let Some(row) = rows.next()? {
+        let text = |idx: Option<usize>| -> Result<String> {
+            Ok(match idx {
+                Some(i) => text_value(row.get_ref(i)?).unwrap_or_default(),
+                None => String::new(),
+            })
+        };
+        let timestamp = timestamp_value(row.get_ref(ts_col)?)?;
+        let id = text(optional[4])?;
+        trades.push(Trade {
+            instrument: Instrument {
+                pair: Pair { base_id: text(optional[1])?, quote_id: text(optional[2])? },
+                exchange: text(optional[0])?,
+                market_type: parse_market_type(&text(optional[3])?),
+            },
+            id: if id.is_empty() { timestamp.to_string() } else { id },
+            price: float_value(row.get_ref(price_col)?, "price")?,
+            amount: float_value(row.get_ref(amount_col)?, "amount")?,
+            side: parse_side(&text(optional[5])?),
+            timestamp: chrono::Utc
+                .timestamp_millis_opt(timestamp)
+                .single()
+                .with_context(|| format!("invalid timestamp {} in {:?}", timestamp, path))?,
+        });
+    }
+    // Порядок строк произвольного запроса не гарантирован
+    trades.sort_by_key(|t| t.timestamp);
+    Ok(trades)
+}
+
+/// Записывает свечи в таблицу `candles` базы SQLite (время — миллисекунды UTC).
+/// Строки обновляются по естественному ключу, поэтому повторные прогоны не создают дублей.
+pub fn write_candles_to_sqlite(path: &Path, candles: &[Candle]) -> Result<()> {
+    if candles.is_empty() {
+        return Ok(());
+    }
+    let mut conn = Connection::open(path).with_context(|| format!("cannot open SQLite database {:?}", path))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS candles (
+            exchange TEXT NOT NULL,
+            market_type TEXT NOT NULL,
+            base_id TEXT NOT NULL,
+            quote_id TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            open REAL,
+            high REAL,
+            low REAL,
+            close REAL,
+            volume REAL,
+            trade_count INTEGER,
+            volume_usdt REAL,
+            PRIMARY KEY (exchange, market_type, base_id, quote_id, interval, timestamp)
+        );
+        CREATE INDEX IF NOT EXISTS candles_interval_timestamp ON candles (interval, timestamp);",
+    )?;
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO candles VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT (exchange, market_type, base_id, quote_id, interval, timestamp) DO UPDATE SET
+                open = excluded.open,
+                high = excluded.high,
+                low = excluded.low,
+                close = excluded.close,
+                volume = excluded.volume,
+                trade_count = excluded.trade_count,
+                volume_usdt = excluded.volume_usdt",
+        )?;
+        for c in candles {
+            stmt.execute(params![
+                c.instrument.exchange,
+                format!("{:?}", c.instrument.market_type),
+                c.instrument.pair.base_id,
+                c.instrument.pair.quote_id,
+                format!("{:?}", c.interval),
+                c.timestamp.timestamp_millis(),
+                c.open,
+                c.high,
+                c.low,
+                c.close,
+                c.volume,
+                c.trade_count as i64,
+                c.volume_usdt,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn process_sqlite_batch(args: &Args) -> Result<()> {
+    let mut pipeline = Pipeline::new(args)?;
+    let inputs = discover_inputs(args, |p| has_extension(p, &SQLITE_EXTENSIONS))?;
+    progress!("Batch symbols: {:?}", inputs.iter().map(|(symbol, _)| symbol).collect::<Vec<_>>());
+    for (symbol, files) in &inputs {
+        pipeline.begin_symbol(symbol, files.len());
+        for file_path in files {
+            let io_start = Instant::now();
+            progress!("  File: {:?}", file_path.file_name().unwrap());
+            let trades = read_trades_from_sqlite(file_path, &args.sqlite_table, args.sqlite_query.as_deref())?;
+            pipeline.stats.io_time += io_start.elapsed();
+            pipeline.process_trades(symbol, file_path, trades)?;
+        }
+    }
+    pipeline.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample_candle;
+    use candle_generator::Timeframe;
+    use std::path::PathBuf;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("candle_batch_aggregator_sqlite_{}_{}.sqlite", name, std::process::id()));
+        std::fs::remove_file(&path).ok();
+        path
+    }
+
+    #[test]
+    fn test_read_trades_from_sqlite() {
+        let path = temp_db("read");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE trades (ts TEXT, timestamp INTEGER, price TEXT, amount REAL, side TEXT, base_id TEXT);
+                 INSERT INTO trades VALUES
+                    ('b', 1714000060000, '50100.5', 0.2, 'sell', 'BTC'),
+                    ('a', 1714000000000, '50000', 0.1, 'buy', 'BTC'),
+                    ('c', 1714000120000, '50200', 0.3, 'buy', 'ETH');",
+            )
+            .unwrap();
+        let trades = read_trades_from_sqlite(&path, "trades", None).unwrap();
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].price, 50000.0);
+        assert_eq!(trades[1].price, 50100.5);
+        assert_eq!(trades[1].side, candle_generator::Side::Sell);
+        let query = "SELECT datetime(timestamp / 1000, 'unixepoch') AS timestamp, price, amount FROM trades WHERE base_id = 'BTC'";
+        let trades = read_trades_from_sqlite(&path, "trades", Some(query)).unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].timestamp.timestamp_millis(), 1714000060000);
+        let err = read_trades_from_sqlite(&path, "trades", Some("SELECT ts, price FROM trades")).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert!(format!("{:#}", err).contains("no column \"timestamp\""));
+    }
+
+    #[test]
+    fn test_write_candles_to_sqlite_upsert() {
+        let candle = |ts, close| sample_candle(Timeframe::m1, ts, close);
+        let path = temp_db("sink");
+        write_candles_to_sqlite(&path, &[candle(1714000020000, 50000.0), candle(1714000080000, 50100.0)]).unwrap();
+        write_candles_to_sqlite(&path, &[candle(1714000080000, 50150.0)]).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        let count: i64 = conn.query_row("SELECT count(*) FROM candles", [], |row| row.get(0)).unwrap();
+        let close: f64 = conn.query_row("SELECT close FROM candles WHERE timestamp = 1714000080000", [], |row| row.get(0)).unwrap();
+        drop(conn);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(count, 2);
+        assert_eq!(close, 50150.0);
+    }
+}
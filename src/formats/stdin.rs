@@ -0,0 +1,33 @@
+use super::super::Args;
+use anyhow::{bail, Result};
+use std::path::Path;
+use crate::pipeline::Pipeline;
+use super::compression::decode_stream;
+use super::{csv, ndjson};
+
+/// Условное имя источника: от него берутся stem выходных файлов и колонка source в отчётах
+const STDIN_SOURCE: &str = "stdin";
+
+/// Пара для трейдов из stdin: `--symbol` должен быть одной парой; без него или с ALL — имя по умолчанию "stdin"
+fn stdin_symbol(args: &Args) -> Result<String> {
+    let symbol = args.symbol.as_deref().unwrap_or("ALL").trim();
+    if symbol.contains(',') {
+        bail!("--input - reads a single symbol, got {:?}", symbol);
+    }
+    Ok(if symbol.eq_ignore_ascii_case("ALL") { STDIN_SOURCE.to_string() } else { symbol.to_string() })
+}
+
+/// Режим конвейера `--input -`: трейды CSV или NDJSON (в том числе gzip/zstd/bz2) из stdin
+pub fn process_stdin(args: &Args) -> Result<()> {
+    let symbol = stdin_symbol(args)?;
+    let mut pipeline = Pipeline::new(args)?;
+    pipeline.begin_symbol(&symbol, 1);
+    let mut reader = decode_stream(std::io::stdin())?;
+    let source = Path::new(STDIN_SOURCE);
+    match args.format.as_str() {
+        "csv" | "auto" => csv::process_csv_reader(&mut pipeline, &symbol, source, &mut reader, &csv::CsvOptions::from_args(args)?)?,
+        "ndjson" | "jsonl" => ndjson::process_ndjson_reader(&mut pipeline, &symbol, source, &mut reader, &ndjson::ndjson_mapping(args)?)?,
+        other => bail!("--input - supports csv, ndjson and auto formats, got {:?}", other),
+    }
+    pipeline.finish()
+}
@@ -0,0 +1,159 @@
+use anyhow::{bail, Context, Result};
+use candle_generator::{Trade, Instrument, Pair, MarketType};
+use chrono::TimeZone;
+use csv::ReaderBuilder;
+use serde::Deserialize;
+use std::io::Read;
+use super::parse_side;
+
+/// По какому времени раскладывать трейды по свечам
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TardisClock {
+    /// Время сделки на бирже (timestamp)
+    Exchange,
+    /// Время получения сообщения коллектором Tardis (local_timestamp)
+    Local,
+}
+
+pub fn parse_clock(value: &str) -> Result<TardisClock> {
+    match value.trim().to_lowercase().as_str() {
+        "exchange" => Ok(TardisClock::Exchange),
+        "local" => Ok(TardisClock::Local),
+        other => bail!("unknown Tardis timestamp {:?} (expected exchange or local)", other),
+    }
+}
+
+/// Строка нормализованного CSV Tardis `trades`; время в микросекундах
+#[derive(Debug, Deserialize)]
+struct TardisTrade {
+    exchange: String,
+    symbol: String,
+    timestamp: i64,
+    local_timestamp: i64,
+    #[serde(default)]
+    id: String,
+    side: String,
+    price: f64,
+    amount: f64,
+}
+
+/// Заголовок нормализованных выгрузок Tardis отличается от прочих колонкой local_timestamp
+pub fn is_tardis_header(first_line: &str) -> bool {
+    first_line.trim().split(',').any(|c| c.trim().eq_ignore_ascii_case("local_timestamp"))
+}
+
+/// Id площадок Tardis, где торгуются только деривативы (фьючерсы, своп, опционы), хотя по названию это не видно
+const DERIVATIVE_EXCHANGES: [&str; 14] = [
+    "bitmex",
+    "deribit",
+    "bybit",
+    "huobi-dm",
+    "huobi-dm-swap",
+    "huobi-dm-linear-swap",
+    "huobi-dm-options",
+    "cryptofacilities",
+    "kraken-futures",
+    "binance-options",
+    "bitfinex-derivatives",
+    "okex-options",
+    "delta",
+    "coinbase-international",
+];
+
+/// Тип рынка по id площадки Tardis: явный список деривативных площадок, затем суффиксы
+/// binance-futures, binance-delivery, okex-swap, okex-futures и т.п.
+fn market_type(exchange: &str) -> MarketType {
+    let exchange = exchange.trim().to_lowercase();
+    if DERIVATIVE_EXCHANGES.contains(&exchange.as_str()) || ["futures", "delivery", "swap", "options"].iter().any(|s| exchange.contains(s)) {
+        MarketType::Futures
+    } else {
+        MarketType::Spot
+    }
+}
+
+/// Читает нормализованный CSV Tardis. Файлы упорядочены по local_timestamp, поэтому
+/// при разбивке по биржевому времени трейды пересортировываются.
+pub fn read_tardis_trades(reader: impl Read, clock: TardisClock) -> Result<Vec<Trade>> {
+    let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(reader);
+    let mut trades = Vec::new();
+    for result in rdr.deserialize() {
+        let row: TardisTrade = result?;
+        let micros = match clock {
+            TardisClock::Exchange => row.timestamp,
+            TardisClock::Local => row.local_timestamp,
+        };
+        let millis = micros.div_euclid(1000);
+        trades.push(Trade {
+            // Символ Tardis (BTCUSDT, BTC-PERPETUAL) не разбивается на base/quote однозначно
+            instrument: Instrument {
+                pair: Pair { base_id: row.symbol, quote_id: String::new() },
+                market_type: market_type(&row.exchange),
+                exchange: row.exchange,
+            },
+            id: if row.id.is_empty() { micros.to_string() } else { row.id },
+            price: row.price,
+            amount: row.amount,
+            side: parse_side(&row.side),
+            timestamp: chrono::Utc
+                .timestamp_millis_opt(millis)
+                .single()
+                .with_context(|| format!("invalid timestamp {}", micros))?,
+        });
+    }
+    if clock == TardisClock::Exchange {
+        trades.sort_by_key(|t| t.timestamp);
+    }
+    Ok(trades)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const DATA: &str = "exchange,symbol,timestamp,local_timestamp,id,side,price,amount\n\
+                        binance-futures,BTCUSDT,1714000059990000,1714000060010000,1,buy,50000.0,0.1\n\
+                        binance-futures,BTCUSDT,1714000059980000,1714000060020000,2,sell,50001.0,0.2\n";
+
+    #[test]
+    fn test_read_tardis_exchange_clock() {
+        let trades = read_tardis_trades(Cursor::new(DATA), TardisClock::Exchange).unwrap();
+        assert_eq!(trades.len(), 2);
+        // Пересортировано по биржевому времени
+        assert_eq!(trades[0].id, "2");
+        assert_eq!(trades[0].timestamp.timestamp_millis(), 1714000059980);
+        assert_eq!(trades[0].instrument.market_type, MarketType::Futures);
+        assert_eq!(trades[0].side, candle_generator::Side::Sell);
+    }
+
+    #[test]
+    fn test_read_tardis_local_clock() {
+        let trades = read_tardis_trades(Cursor::new(DATA), TardisClock::Local).unwrap();
+        assert_eq!(trades[0].id, "1");
+        // По времени получения оба трейда попадают в следующую минуту
+        assert!(trades.iter().all(|t| t.timestamp.timestamp_millis() >= 1714000060000));
+        assert!(is_tardis_header(DATA.lines().next().unwrap()));
+        assert!(parse_clock("wall").is_err());
+    }
+
+    #[test]
+    fn test_tardis_market_type() {
+        for exchange in [
+            "bitmex",
+            "deribit",
+            "bybit",
+            "huobi-dm",
+            "cryptofacilities",
+            "kraken-futures",
+            "binance-options",
+            "bitfinex-derivatives",
+            "binance-futures",
+            "okex-swap",
+        ] {
+            assert_eq!(market_type(exchange), MarketType::Futures, "{}", exchange);
+        }
+        for exchange in ["binance", "coinbase", "bybit-spot", "huobi", "kraken", "bitfinex"] {
+            assert_eq!(market_type(exchange), MarketType::Spot, "{}", exchange);
+        }
+    }
+}
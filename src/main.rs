@@ -1,56 +1,26 @@
-mod aggregation;
-mod stats;
-mod chain;
-mod formats {
-    pub mod csv;
-    pub mod parquet;
-    pub mod duckdb;
-    pub mod questdb;
-    pub mod clickhouse;
-}
-
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{CommandFactory, Parser};
 use anyhow::Result;
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Root directory containing trade history files
-    #[arg(short = 'i', long)]
-    input: PathBuf,
-
-    /// Output directory for candles (optional)
-    #[arg(short = 'o', long)]
-    output: Option<PathBuf>,
-
-    /// Trading pair symbols (comma-separated) or "ALL" for all
-    #[arg(short = 's', long)]
-    symbol: String,
-
-    /// Candle intervals in minutes (comma-separated or "ALL")
-    #[arg(short = 't', long, default_value = "1")]
-    interval: String,
-
-    /// Input format (csv/parquet/duckdb/questdb/clickhouse/auto)
-    #[arg(short = 'f', long, default_value = "csv")]
-    format: String,
-
-    /// Enable detailed performance metrics
-    #[arg(short = 'b', long)]
-    benchmark: bool,
-
-    /// Show progress for each file
-    #[arg(short = 'p', long)]
-    progress: bool,
-
-    /// Print memory usage statistics
-    #[arg(short = 'm', long)]
-    memory_stats: bool,
-}
+use candle_batch_aggregator::{formats, reconcile, Args};
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    // --symbol можно не указывать только для stdin: там одна пара
+    if args.symbol.is_none() && args.input.as_os_str() != "-" {
+        Args::command()
+            .error(clap::error::ErrorKind::MissingRequiredArgument, "--symbol <SYMBOL> is required unless --input -")
+            .exit();
+    }
+    if let Some(reference) = &args.reconcile {
+        if !reconcile::run_reconcile(&args, reference)? {
+            eprintln!("Reconcile: differences exceed tolerances");
+            std::process::exit(2);
+        }
+        return Ok(());
+    }
+    // Конвейер: трейды из stdin вместо директории с парами
+    if args.input.as_os_str() == "-" {
+        return formats::stdin::process_stdin(&args);
+    }
     // Поддержка разных форматов
     match args.format.as_str() {
         "csv" => formats::csv::process_csv_batch(&args)?,
@@ -58,8 +28,12 @@ fn main() -> Result<()> {
         "duckdb" => formats::duckdb::process_duckdb_batch(&args)?,
         "questdb" => formats::questdb::process_questdb_batch(&args)?,
         "clickhouse" => formats::clickhouse::process_clickhouse_batch(&args)?,
+        "sqlite" => formats::sqlite::process_sqlite_batch(&args)?,
+        "arrow" => formats::ipc::process_ipc_batch(&args)?,
+        "ndjson" | "jsonl" => formats::ndjson::process_ndjson_batch(&args)?,
+        "auto" => formats::auto::process_auto_batch(&args)?,
         _ => {
-            eprintln!("Only CSV, Parquet, DuckDB, QuestDB and ClickHouse formats supported in MVP");
+            eprintln!("Unknown input format {:?} (supported: csv, parquet, duckdb, sqlite, questdb, clickhouse, arrow, ndjson, auto)", args.format);
             std::process::exit(1);
         }
     }
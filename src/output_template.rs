@@ -0,0 +1,250 @@
+use super::Args;
+use anyhow::{bail, Result};
+use candle_generator::{Candle, Timeframe};
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+use crate::aggregation::timeframe_millis;
+
+/// Готовые шаблоны `--output-template`; default повторяет прежнюю раскладку
+pub const OUTPUT_PRESETS: [(&str, &str); 5] = [
+    ("default", "{symbol}_{tf}/{stem}_{tf}.{ext}"),
+    ("flat", "{symbol}_{tf}_{stem}.{ext}"),
+    ("hive", "exchange={exchange}/market={market}/symbol={symbol}/interval={interval}/date={date}/{stem}.{ext}"),
+    ("by-date", "{exchange}/{symbol}/{interval}/{year}/{month}/{day}/{stem}.{ext}"),
+    // Как дневные klines data.binance.vision: BTCUSDT/1m/BTCUSDT-1m-2024-05-01.csv.
+    // Без {stem}: рассчитан на один источник на пару и день (дневные или месячные выгрузки)
+    ("binance", "{symbol}/{interval}/{symbol}-{interval}-{date}.{ext}"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Exchange,
+    Market,
+    Base,
+    Quote,
+    Symbol,
+    Tf,
+    Interval,
+    Minutes,
+    Date,
+    Year,
+    Month,
+    Day,
+    Hour,
+    Stem,
+    Ext,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "exchange" => Self::Exchange,
+            "market" => Self::Market,
+            "base" => Self::Base,
+            "quote" => Self::Quote,
+            "symbol" => Self::Symbol,
+            "tf" | "timeframe" => Self::Tf,
+            "interval" => Self::Interval,
+            "minutes" => Self::Minutes,
+            "date" => Self::Date,
+            "year" => Self::Year,
+            "month" => Self::Month,
+            "day" => Self::Day,
+            "hour" => Self::Hour,
+            "stem" => Self::Stem,
+            "ext" => Self::Ext,
+            _ => return None,
+        })
+    }
+
+    /// Значение берётся из самой свечи (инструмент или время), а не из источника
+    fn per_candle(self) -> bool {
+        matches!(self, Self::Exchange | Self::Market | Self::Base | Self::Quote | Self::Date | Self::Year | Self::Month | Self::Day | Self::Hour)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// Таймфрейм в записи бирж: 1m, 4h, 1d
+fn interval_name(tf: &Timeframe) -> String {
+    let minutes = timeframe_millis(tf) / 60_000;
+    match minutes {
+        m if m % 1440 == 0 => format!("{}d", m / 1440),
+        m if m % 60 == 0 => format!("{}h", m / 60),
+        m => format!("{}m", m),
+    }
+}
+
+/// Значения подставляются в путь, поэтому разделители директорий в них заменяются,
+/// а `.` и `..` (текущая и родительская директория) — на подчёркивания
+fn path_value(value: &str) -> String {
+    match value {
+        "." | ".." => value.replace('.', "_"),
+        _ => value.replace(['/', '\\'], "-"),
+    }
+}
+
+/// Что известно о файле свечей без самих свечей
+pub struct OutputContext<'a> {
+    pub symbol: &'a str,
+    pub stem: &'a str,
+    pub tf: &'a Timeframe,
+    pub ext: &'a str,
+}
+
+/// Шаблон пути выходного файла относительно `--output`, например `{symbol}/{interval}/{date}.{ext}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTemplate {
+    segments: Vec<Segment>,
+}
+
+impl OutputTemplate {
+    /// Имя пресета из `OUTPUT_PRESETS` или шаблон с плейсхолдерами `{name}`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let template = OUTPUT_PRESETS.iter().find(|(name, _)| *name == spec).map(|(_, t)| *t).unwrap_or(spec);
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                bail!("unmatched '}}' in output template {:?}", template);
+            }
+            let Some(close) = rest[open..].find('}').map(|i| open + i) else {
+                bail!("unclosed '{{' in output template {:?}", template);
+            };
+            let name = &rest[open + 1..close];
+            let Some(field) = Field::parse(name) else {
+                bail!("unknown placeholder {{{}}} in output template {:?}", name, template);
+            };
+            if open > 0 {
+                segments.push(Segment::Literal(rest[..open].to_string()));
+            }
+            segments.push(Segment::Field(field));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        let literal: String = segments.iter().filter_map(|s| match s { Segment::Literal(l) => Some(l.as_str()), _ => None }).collect();
+        if Path::new(template).is_absolute() || Path::new(&literal).components().any(|c| c == Component::ParentDir) {
+            bail!("output template {:?} must be a relative path inside --output", template);
+        }
+        if !segments.iter().any(|s| matches!(s, Segment::Field(_))) {
+            bail!("output template {:?} has no placeholders: every file would overwrite the previous one", template);
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn from_args(args: &Args) -> Result<Self> {
+        Self::parse(&args.output_template)
+    }
+
+    fn per_candle(&self) -> bool {
+        self.segments.iter().any(|s| matches!(s, Segment::Field(f) if f.per_candle()))
+    }
+
+    fn render(&self, ctx: &OutputContext, candle: Option<&Candle>) -> PathBuf {
+        let mut path = String::new();
+        for segment in &self.segments {
+            let field = match segment {
+                Segment::Literal(l) => {
+                    path.push_str(l);
+                    continue;
+                }
+                Segment::Field(field) => *field,
+            };
+            let value = match (field, candle) {
+                (Field::Symbol, _) => path_value(ctx.symbol),
+                (Field::Stem, _) => path_value(ctx.stem),
+                (Field::Ext, _) => ctx.ext.to_string(),
+                (Field::Tf, _) => format!("{:?}", ctx.tf),
+                (Field::Interval, _) => interval_name(ctx.tf),
+                (Field::Minutes, _) => (timeframe_millis(ctx.tf) / 60_000).to_string(),
+                (Field::Exchange, Some(c)) => path_value(&c.instrument.exchange),
+                (Field::Market, Some(c)) => format!("{:?}", c.instrument.market_type).to_lowercase(),
+                (Field::Base, Some(c)) => path_value(&c.instrument.pair.base_id),
+                (Field::Quote, Some(c)) => path_value(&c.instrument.pair.quote_id),
+                (Field::Date, Some(c)) => c.timestamp.format("%Y-%m-%d").to_string(),
+                (Field::Year, Some(c)) => c.timestamp.format("%Y").to_string(),
+                (Field::Month, Some(c)) => c.timestamp.format("%m").to_string(),
+                (Field::Day, Some(c)) => c.timestamp.format("%d").to_string(),
+                (Field::Hour, Some(c)) => c.timestamp.format("%H").to_string(),
+                (_, None) => String::new(),
+            };
+            // Пустое значение (например, свеча без биржи) не должно превращаться в пустую директорию
+            path.push_str(if value.is_empty() { "unknown" } else { &value });
+        }
+        PathBuf::from(path)
+    }
+
+    /// Раскладывает свечи по файлам: шаблон без полей свечи даёт один файл (даже для пустого набора),
+    /// иначе свечи группируются по отрендеренному пути в порядке первого появления
+    pub fn split<'c>(&self, ctx: &OutputContext, candles: &'c [Candle]) -> Vec<(PathBuf, Cow<'c, [Candle]>)> {
+        if !self.per_candle() {
+            return vec![(self.render(ctx, None), Cow::Borrowed(candles))];
+        }
+        let mut groups: Vec<(PathBuf, Vec<Candle>)> = Vec::new();
+        for candle in candles {
+            let path = self.render(ctx, Some(candle));
+            // Свечи отсортированы по времени, поэтому обычно подходит последняя группа
+            match groups.iter_mut().rev().find(|(p, _)| *p == path) {
+                Some((_, group)) => group.push(candle.clone()),
+                None => groups.push((path, vec![candle.clone()])),
+            }
+        }
+        groups.into_iter().map(|(path, group)| (path, Cow::Owned(group))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample_candle;
+
+    #[test]
+    fn test_output_template_split() {
+        let ctx = OutputContext { symbol: "BTCUSDT", stem: "trades", tf: &Timeframe::h1, ext: "csv" };
+        // 2024-05-01 23:00 и 2024-05-02 00:00, 01:00
+        let candles = [1714604400000, 1714608000000, 1714611600000].map(|ts| sample_candle(Timeframe::h1, ts, 1.0));
+        let default = OutputTemplate::parse("default").unwrap().split(&ctx, &candles);
+        assert_eq!(default.len(), 1);
+        assert_eq!(default[0].0, PathBuf::from("BTCUSDT_h1/trades_h1.csv"));
+        assert_eq!(default[0].1.len(), 3);
+        let hive = OutputTemplate::parse("hive").unwrap().split(&ctx, &candles);
+        let paths: Vec<_> = hive.iter().map(|(p, c)| (p.to_string_lossy().to_string(), c.len())).collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("exchange=binance/market=spot/symbol=BTCUSDT/interval=1h/date=2024-05-01/trades.csv".to_string(), 1),
+                ("exchange=binance/market=spot/symbol=BTCUSDT/interval=1h/date=2024-05-02/trades.csv".to_string(), 2),
+            ]
+        );
+        let custom = OutputTemplate::parse("{base}-{quote}/{minutes}/{year}{month}{day}.{ext}").unwrap().split(&ctx, &candles[..1]);
+        assert_eq!(custom[0].0, PathBuf::from("BTC-USDT/60/20240501.csv"));
+    }
+
+    #[test]
+    fn test_output_template_errors() {
+        assert!(OutputTemplate::parse("{symbol}/{nope}.csv").is_err());
+        assert!(OutputTemplate::parse("{symbol/x.csv").is_err());
+        assert!(OutputTemplate::parse("../{symbol}.csv").is_err());
+        assert!(OutputTemplate::parse("candles.csv").is_err());
+    }
+
+    #[test]
+    fn test_output_template_path_values() {
+        assert_eq!(path_value(".."), "__");
+        assert_eq!(path_value("."), "_");
+        assert_eq!(path_value("a/b\\c"), "a-b-c");
+        assert_eq!(path_value("..BTC"), "..BTC");
+        let ctx = OutputContext { symbol: "..", stem: ".", tf: &Timeframe::m1, ext: "csv" };
+        let mut c = sample_candle(Timeframe::h1, 1714604400000, 1.0);
+        c.instrument.exchange = "..".to_string();
+        let paths = OutputTemplate::parse("{exchange}/{symbol}/{stem}.{ext}").unwrap().split(&ctx, std::slice::from_ref(&c));
+        assert_eq!(paths[0].0, PathBuf::from("__/__/_.csv"));
+        assert!(paths[0].0.components().all(|c| matches!(c, Component::Normal(_))));
+    }
+}
@@ -0,0 +1,427 @@
+use super::Args;
+use anyhow::Result;
+use candle_generator::{Candle, Timeframe, Trade};
+use csv::Writer;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::aggregation::{self, TradeExtra};
+use crate::discovery::{self, Partition};
+use crate::filter::{self, FilterConfig};
+use crate::output_template::{OutputContext, OutputTemplate};
+use crate::formats::csv::parse_intervals;
+use crate::formats::clickhouse::ClickhouseSink;
+use crate::formats::compression;
+use crate::formats::duckdb::{DuckdbLayout, DuckdbSink};
+use crate::formats::ipc;
+use crate::formats::sqlite;
+use crate::formats::postgres::PostgresSink;
+use crate::formats::parquet::{self, ParquetWriteOptions};
+use crate::formats::questdb::QuestdbSink;
+use crate::side;
+use crate::progress;
+use crate::stats::{ProcessingStats, print_summary};
+use crate::validation;
+use crate::verify;
+
+pub const OUTPUT_FORMATS: [&str; 9] = ["csv", "parquet", "arrow", "arrow-stream", "duckdb", "sqlite", "questdb", "clickhouse", "postgres"];
+
+/// Форматы, которые можно писать в stdout (`--output -`)
+pub const STDOUT_FORMATS: [&str; 4] = ["csv", "parquet", "arrow", "arrow-stream"];
+
+/// Пары для обработки: перечисленные через запятую или все поддиректории входной директории для ALL
+pub fn resolve_symbols(args: &Args) -> Result<Vec<String>> {
+    let symbol = args.symbol()?;
+    if symbol.to_uppercase() == "ALL" {
+        Ok(fs::read_dir(&args.input)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect())
+    } else {
+        Ok(symbol.split(',').map(|s| s.trim().to_string()).collect())
+    }
+}
+
+/// Все файлы в директории пары, по имени
+pub fn dir_files(symbol_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<_> = fs::read_dir(symbol_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Файл с одним из расширений `extensions`
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().is_some_and(|ext| extensions.iter().any(|e| ext == *e))
+}
+
+/// Трейды в хронологическом порядке (сортировка устойчивая, `extras` переставляются вместе с трейдами).
+/// NDJSON, своя раскладка CSV и локальное время Tardis порядок не гарантируют, а фильтр по медиане,
+/// tick rule и агрегация на него рассчитывают.
+fn sort_by_time(trades: Vec<Trade>, extras: Option<Vec<TradeExtra>>) -> (Vec<Trade>, Option<Vec<TradeExtra>>) {
+    if trades.windows(2).all(|w| w[0].timestamp <= w[1].timestamp) {
+        return (trades, extras);
+    }
+    let mut order: Vec<usize> = (0..trades.len()).collect();
+    order.sort_by_key(|&i| trades[i].timestamp);
+    (permute(trades, &order), extras.map(|e| permute(e, &order)))
+}
+
+fn permute<T>(items: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut slots: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    order.iter().map(|&i| slots[i].take().expect("order is a permutation")).collect()
+}
+
+/// Общий пайплайн для всех входных форматов: фильтрация, определение стороны,
+/// агрегация цепочкой, самопроверка, валидация и запись свечей.
+/// Адаптеры только читают трейды и передают их в `process_trades`.
+pub struct Pipeline<'a> {
+    args: &'a Args,
+    pub intervals: Vec<Timeframe>,
+    pub out_root: PathBuf,
+    pub stats: ProcessingStats,
+    validate: bool,
+    filter_cfg: Option<FilterConfig>,
+    rejects: Option<Writer<File>>,
+    violations: Vec<validation::Violation>,
+    mismatches: Vec<verify::Mismatch>,
+    symbols_seen: usize,
+    verify_symbol: bool,
+    parquet_options: ParquetWriteOptions,
+    questdb_sink: QuestdbSink,
+    clickhouse_sink: ClickhouseSink,
+    postgres_sink: PostgresSink,
+    duckdb_sink: DuckdbSink,
+    output_template: OutputTemplate,
+    /// Файлы свечей, уже записанные в этом прогоне: повторная запись стёрла бы свечи прежнего источника
+    written_files: HashSet<PathBuf>,
+    /// При `--output -`: свечи по позиции таймфрейма в `intervals`, пишутся в stdout в `finish`
+    stdout_candles: Option<Vec<Vec<Candle>>>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(args: &'a Args) -> Result<Self> {
+        if !OUTPUT_FORMATS.contains(&args.output_format.as_str()) {
+            anyhow::bail!("Unknown output format {:?} (supported: {})", args.output_format, OUTPUT_FORMATS.join(", "));
+        }
+        let parquet_options = ParquetWriteOptions {
+            compression: parquet::parse_compression(&args.parquet_compression)?,
+            row_group_size: args.parquet_row_group_size,
+        };
+        let to_stdout = args.output.as_deref() == Some(Path::new("-"));
+        if to_stdout && !STDOUT_FORMATS.contains(&args.output_format.as_str()) {
+            anyhow::bail!("--output - supports only {} output formats", STDOUT_FORMATS.join(", "));
+        }
+        crate::set_progress_to_stderr(to_stdout);
+        let questdb_sink = QuestdbSink::from_args(args)?;
+        let output_template = OutputTemplate::from_args(args)?;
+        let clickhouse_sink = ClickhouseSink::from_args(args)?;
+        let mut stats = ProcessingStats::new();
+        stats.start();
+        // Отчёты (rejects, validation, verify) при выводе в stdout пишутся в директорию по умолчанию
+        let out_root = args.output.clone().filter(|_| !to_stdout).unwrap_or_else(|| PathBuf::from("candles"));
+        let duckdb_sink = DuckdbSink::new(out_root.join("candles.duckdb"), DuckdbLayout::parse(&args.duckdb_layout)?);
+        let intervals = parse_intervals(&args.interval);
+        let filter_cfg = if args.filter || args.filter_sigma.is_some() || args.filter_pct.is_some() {
+            Some(FilterConfig {
+                max_sigma: args.filter_sigma,
+                max_deviation_pct: args.filter_pct,
+                window: args.filter_window,
+            })
+        } else {
+            None
+        };
+        let rejects = match filter_cfg {
+            Some(_) => {
+                fs::create_dir_all(&out_root)?;
+                Some(filter::rejects_writer(out_root.join("rejects.csv"))?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            args,
+            stdout_candles: to_stdout.then(|| vec![Vec::new(); intervals.len()]),
+            intervals,
+            out_root,
+            stats,
+            validate: args.validate || args.fail_on_invalid,
+            filter_cfg,
+            rejects,
+            violations: Vec::new(),
+            mismatches: Vec::new(),
+            symbols_seen: 0,
+            verify_symbol: false,
+            parquet_options,
+            questdb_sink,
+            clickhouse_sink,
+            postgres_sink: PostgresSink::from_args(args),
+            duckdb_sink,
+            output_template,
+            written_files: HashSet::new(),
+        })
+    }
+
+    pub fn args(&self) -> &'a Args {
+        self.args
+    }
+
+    pub fn begin_symbol(&mut self, symbol: &str, files: usize) {
+        self.verify_symbol = self.args.verify && self.symbols_seen.is_multiple_of(self.args.verify_every.max(1));
+        self.symbols_seen += 1;
+        progress!("\nProcessing symbol: {} ({} files)", symbol, files);
+    }
+
+    /// Обрабатывает трейды одного источника. `source` — путь к файлу (или условное имя
+    /// источника для баз данных), его stem используется в имени выходного файла.
+    pub fn process_trades(&mut self, symbol: &str, source: &Path, trades: Vec<Trade>) -> Result<()> {
+        self.process_trades_with_extras(symbol, source, trades, None)
+    }
+
+    /// Как `process_trades`, но с дополнительными данными по каждому трейду (см. `TradeExtra`)
+    pub fn process_trades_with_extras(
+        &mut self,
+        symbol: &str,
+        source: &Path,
+        mut trades: Vec<Trade>,
+        mut extras: Option<Vec<TradeExtra>>,
+    ) -> Result<()> {
+        let args = self.args;
+        let source_name = source.file_name().unwrap_or(source.as_os_str()).to_string_lossy().to_string();
+        (trades, extras) = sort_by_time(trades, extras);
+        let mut stem = compression::source_stem(source);
+        // Hive-партиции в пути относительно --input дополняют инструмент и дату трейдов
+        if let Ok(relative) = source.strip_prefix(&args.input) {
+            Partition::from_path(relative).fill(&mut trades);
+            stem = discovery::nested_stem(relative, symbol, &stem);
+        }
+        self.stats.add_file();
+        self.stats.add_trades(trades.len());
+        progress!("    Trades: {}", trades.len());
+        if let (Some(cfg), Some(wtr)) = (&self.filter_cfg, self.rejects.as_mut()) {
+            let (keep, rejected) = filter::filter_mask(&trades, cfg, symbol, &source_name);
+            for r in &rejected {
+                self.stats.add_rejected(r.reason);
+                wtr.serialize(r)?;
+            }
+            if !rejected.is_empty() {
+                progress!("    Rejected: {}", rejected.len());
+                trades = filter::retain_by_mask(trades, &keep);
+                extras = extras.map(|e| filter::retain_by_mask(e, &keep));
+            }
+        }
+        if args.infer_side {
+            let inferred = side::infer_sides(&mut trades);
+            self.stats.add_inferred_sides(inferred);
+            if inferred > 0 {
+                progress!("    Inferred sides: {}", inferred);
+            }
+        }
+        let agg_start = Instant::now();
+        let chain = aggregation::aggregate_trades_chain_with_extras(trades.iter(), &self.intervals, extras.as_deref());
+        self.stats.aggregation_time += agg_start.elapsed();
+        if self.verify_symbol {
+            let found = verify::verify_chain(trades.iter(), extras.as_deref(), &chain, &self.intervals, symbol, &source_name);
+            if !found.is_empty() {
+                progress!("    Verify: {} mismatches between chained and direct aggregation", found.len());
+                for m in found.iter().take(5) {
+                    progress!("      [{}] {} {}", m.timeframe, m.timestamp, m.field);
+                }
+            }
+            self.stats.add_verify_mismatches(found.len());
+            self.mismatches.extend(found);
+        }
+        if self.validate {
+            let found = validation::validate_chain(symbol, &source_name, &chain, &self.intervals);
+            if !found.is_empty() {
+                progress!("    Validation: {} violations", found.len());
+            }
+            self.stats.add_violations(found.len());
+            self.violations.extend(found);
+            if args.fail_on_invalid && !self.violations.is_empty() {
+                finish_validation(&self.out_root, &self.violations)?;
+                anyhow::bail!("Candle validation failed for {:?}: {} violations", source, self.violations.len());
+            }
+        }
+        for (tf, candles) in chain {
+            self.stats.add_candles(&format!("{:?}", tf), candles.len());
+            let io_start = Instant::now();
+            let target = self.write_candles(symbol, &stem, &tf, &candles)?;
+            self.stats.io_time += io_start.elapsed();
+            progress!("    [{:?}] Candles: {} -> {}", tf, candles.len(), target);
+        }
+        Ok(())
+    }
+
+    /// Пишет свечи одного таймфрейма в выбранный `--output-format`, возвращает описание места записи
+    fn write_candles(&mut self, symbol: &str, stem: &str, tf: &Timeframe, candles: &[Candle]) -> Result<String> {
+        let args = self.args;
+        if let Some(buffers) = self.stdout_candles.as_mut() {
+            let idx = self.intervals.iter().position(|t| t == tf).unwrap_or_default();
+            buffers[idx].extend_from_slice(candles);
+            return Ok("stdout".to_string());
+        }
+        match args.output_format.as_str() {
+            "duckdb" => {
+                self.duckdb_sink.write_candles(candles)?;
+                Ok(format!("{:?}", self.duckdb_sink.path()))
+            }
+            "sqlite" => {
+                fs::create_dir_all(&self.out_root)?;
+                let db_path = self.out_root.join("candles.sqlite");
+                sqlite::write_candles_to_sqlite(&db_path, candles)?;
+                Ok(format!("{:?}", db_path))
+            }
+            "questdb" => {
+                self.questdb_sink.write_candles(candles, symbol)?;
+                Ok(format!("{} ({})", args.questdb_ilp, self.questdb_sink.table()))
+            }
+            "clickhouse" => {
+                self.clickhouse_sink.write_candles(candles, symbol)?;
+                Ok(format!("{} ({})", args.clickhouse_url, self.clickhouse_sink.table()))
+            }
+            "postgres" => {
+                self.postgres_sink.write_candles(candles)?;
+                Ok(format!("PostgreSQL ({})", self.postgres_sink.table()))
+            }
+            format => {
+                // Потоковый Arrow IPC принято называть .arrows
+                let extension = if format == "arrow-stream" { "arrows" } else { format };
+                let ctx = OutputContext { symbol, stem, tf, ext: extension };
+                let mut targets = Vec::new();
+                for (relative, part) in self.output_template.split(&ctx, candles) {
+                    let out_file = self.out_root.join(relative);
+                    if let Some(out_dir) = out_file.parent() {
+                        fs::create_dir_all(out_dir)?;
+                    }
+                    if !self.written_files.insert(out_file.clone()) {
+                        anyhow::bail!(
+                            "{:?} was already written in this run from another source; writing it again would lose those candles (add {{stem}} to --output-template)",
+                            out_file
+                        );
+                    }
+                    match format {
+                        "parquet" => parquet::write_candles_parquet(&part, &out_file, &self.parquet_options)?,
+                        "arrow" => ipc::write_candles_ipc(&part, &out_file, false)?,
+                        "arrow-stream" => ipc::write_candles_ipc(&part, &out_file, true)?,
+                        _ => aggregation::write_candles_csv(&part, &out_file)?,
+                    }
+                    targets.push(out_file);
+                }
+                Ok(match targets.as_slice() {
+                    [] => "no files".to_string(),
+                    [single] => format!("{:?}", single),
+                    [first, ..] => format!("{} files ({:?}, ...)", targets.len(), first),
+                })
+            }
+        }
+    }
+
+    /// Свечи всех таймфреймов одним потоком: при нескольких таймфреймах CSV получает колонку interval
+    /// (в Parquet и Arrow она есть всегда)
+    fn write_stdout(&self, candles: Vec<Candle>) -> Result<()> {
+        let stdout = std::io::stdout();
+        match self.args.output_format.as_str() {
+            "parquet" => parquet::write_candles_parquet_to(&candles, stdout, &self.parquet_options),
+            "arrow" => ipc::write_candles_ipc_to(&candles, stdout, false),
+            "arrow-stream" => ipc::write_candles_ipc_to(&candles, stdout, true),
+            _ => aggregation::write_candles_csv_to(&candles, stdout, self.intervals.len() > 1),
+        }
+    }
+
+    /// Дописывает отчёты и печатает итоговую статистику
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(mut wtr) = self.rejects.take() {
+            wtr.flush()?;
+        }
+        if self.validate {
+            finish_validation(&self.out_root, &self.violations)?;
+        }
+        if self.args.verify {
+            finish_verify(&self.out_root, &self.mismatches)?;
+        }
+        if let Some(buffers) = self.stdout_candles.take() {
+            let io_start = Instant::now();
+            self.write_stdout(buffers.concat())?;
+            self.stats.io_time += io_start.elapsed();
+        }
+        self.stats.stop();
+        print_summary(&self.stats);
+        Ok(())
+    }
+}
+
+fn finish_verify(out_root: &Path, mismatches: &[verify::Mismatch]) -> Result<()> {
+    if mismatches.is_empty() {
+        progress!("\nVerify: chained and direct aggregation agree");
+        return Ok(());
+    }
+    fs::create_dir_all(out_root)?;
+    let report_path = out_root.join("verify_report.csv");
+    verify::write_report(mismatches, &report_path)?;
+    progress!("\nVerify: {} mismatches -> {:?}", mismatches.len(), report_path);
+    Ok(())
+}
+
+fn finish_validation(out_root: &Path, violations: &[validation::Violation]) -> Result<()> {
+    if violations.is_empty() {
+        progress!("\nValidation: all candles passed");
+        return Ok(());
+    }
+    fs::create_dir_all(out_root)?;
+    let report_path = out_root.join("validation_report.csv");
+    validation::write_report(violations, &report_path)?;
+    progress!("\nValidation: {} violations -> {:?}", violations.len(), report_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_generator::{Instrument, MarketType, Pair, Side};
+    use chrono::TimeZone;
+
+    fn trade(ts: i64, id: &str) -> Trade {
+        Trade {
+            instrument: Instrument {
+                pair: Pair { base_id: "BTC".to_string(), quote_id: "USDT".to_string() },
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+            },
+            id: id.to_string(),
+            price: 50000.0,
+            amount: 0.1,
+            side: Side::Unknown,
+            timestamp: chrono::Utc.timestamp_millis_opt(ts).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_sort_by_time_keeps_extras_aligned() {
+        let trades = vec![trade(3000, "c"), trade(1000, "a1"), trade(2000, "b"), trade(1000, "a2")];
+        let extras = [3, 1, 2, 4].map(|n| TradeExtra { quote_volume: None, trade_count: Some(n) }).to_vec();
+        let (trades, extras) = sort_by_time(trades, Some(extras));
+        let ids: Vec<&str> = trades.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["a1", "a2", "b", "c"]);
+        let counts: Vec<u64> = extras.unwrap().iter().filter_map(|e| e.trade_count).collect();
+        assert_eq!(counts, [1, 4, 2, 3]);
+    }
+
+    #[test]
+    fn test_repeated_output_path_is_an_error() {
+        let out = std::env::temp_dir().join(format!("candle_batch_aggregator_repeated_{}", std::process::id()));
+        let args = Args { output: Some(out.clone()), output_template: "{symbol}/{interval}.{ext}".to_string(), ..Args::default() };
+        let mut pipeline = Pipeline::new(&args).unwrap();
+        pipeline.process_trades("BTCUSDT", Path::new("day-1.csv"), vec![trade(1714000020000, "1")]).unwrap();
+        // Второй источник отрендерился в тот же файл: вместо молчаливой перезаписи — ошибка
+        let err = pipeline.process_trades("BTCUSDT", Path::new("day-2.csv"), vec![trade(1714086420000, "2")]).unwrap_err();
+        let written = fs::read_to_string(out.join("BTCUSDT/1m.csv")).unwrap();
+        fs::remove_dir_all(&out).ok();
+        assert!(err.to_string().contains("already written"));
+        assert!(written.contains("1714000020000"));
+    }
+}
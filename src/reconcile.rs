@@ -0,0 +1,301 @@
+use super::Args;
+use anyhow::{bail, Context, Result};
+use csv::{ReaderBuilder, WriterBuilder};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use crate::aggregation::timeframe_millis;
+use crate::formats::binance::binance_millis;
+use crate::formats::csv::parse_intervals;
+
+/// Свеча в общем виде: первые шесть колонок совпадают у нашего CSV
+/// (timestamp,open,high,low,close,volume) и у klines Binance (open_time,open,high,low,close,volume,...).
+/// Число сделок — колонка `trade_count` по заголовку, иначе девятая колонка klines Binance (number of trades)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KlineRow {
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub trade_count: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerances {
+    /// Относительный допуск для open/high/low/close
+    pub price: f64,
+    /// Относительный допуск для volume
+    pub volume: f64,
+    /// Относительный допуск для числа сделок
+    pub trade_count: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Difference {
+    pub timestamp: i64,
+    pub kind: &'static str,
+    pub field: &'static str,
+    pub ours: Option<f64>,
+    pub reference: Option<f64>,
+    pub diff: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FieldSummary {
+    pub compared: usize,
+    pub mismatches: usize,
+    pub max_abs_diff: f64,
+    pub sum_abs_diff: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct ReconcileReport {
+    pub matched: usize,
+    pub missing: usize,
+    pub extra: usize,
+    /// Свечи эталона без сделок (volume = 0), которых у нас быть не может
+    pub skipped_empty: usize,
+    pub fields: BTreeMap<&'static str, FieldSummary>,
+    pub differences: Vec<Difference>,
+}
+
+impl ReconcileReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing == 0 && self.extra == 0 && self.fields.values().all(|f| f.mismatches == 0)
+    }
+}
+
+pub fn load_klines<R: Read>(reader: R) -> Result<Vec<KlineRow>> {
+    let mut rdr = ReaderBuilder::new().has_headers(false).flexible(true).from_reader(reader);
+    let mut rows = Vec::new();
+    let mut trade_count_column = 8;
+    for (idx, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line = record.position().map_or(idx as u64 + 1, |p| p.line());
+        let first = record.get(0).unwrap_or_default().trim();
+        let timestamp = match first.parse::<i64>() {
+            Ok(timestamp) => timestamp,
+            // Заголовок (если есть) — только первая строка
+            Err(_) if idx == 0 => {
+                if let Some(i) = record.iter().position(|name| name.trim().eq_ignore_ascii_case("trade_count")) {
+                    trade_count_column = i;
+                }
+                continue;
+            }
+            Err(_) => bail!("line {}: invalid open time {:?}", line, first),
+        };
+        let field = |i: usize| -> Result<f64> {
+            let raw = record.get(i).with_context(|| format!("line {}: missing column {}", line, i))?;
+            raw.trim().parse::<f64>().with_context(|| format!("line {}: invalid number {:?}", line, raw))
+        };
+        rows.push(KlineRow {
+            timestamp: binance_millis(timestamp),
+            open: field(1)?,
+            high: field(2)?,
+            low: field(3)?,
+            close: field(4)?,
+            volume: field(5)?,
+            trade_count: record.get(trade_count_column).and_then(|s| s.trim().parse().ok()),
+        });
+    }
+    Ok(rows)
+}
+
+/// Загружает свечи из файла или из всех CSV в директории
+pub fn load_klines_path(path: &Path) -> Result<Vec<KlineRow>> {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        let mut files: Vec<_> = fs::read_dir(path)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "csv"))
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut rows = Vec::new();
+    for file in files {
+        let f = File::open(&file).with_context(|| format!("cannot open {:?}", file))?;
+        rows.extend(load_klines(f).with_context(|| format!("cannot parse {:?}", file))?);
+    }
+    Ok(rows)
+}
+
+fn compare_field(report: &mut ReconcileReport, timestamp: i64, field: &'static str, ours: f64, reference: f64, tolerance: f64) {
+    let diff = ours - reference;
+    let summary = report.fields.entry(field).or_default();
+    summary.compared += 1;
+    summary.sum_abs_diff += diff.abs();
+    summary.max_abs_diff = summary.max_abs_diff.max(diff.abs());
+    if diff.abs() > tolerance * reference.abs() {
+        summary.mismatches += 1;
+        report.differences.push(Difference {
+            timestamp,
+            kind: "mismatch",
+            field,
+            ours: Some(ours),
+            reference: Some(reference),
+            diff: Some(diff),
+        });
+    }
+}
+
+pub fn reconcile(ours: &[KlineRow], reference: &[KlineRow], tol: Tolerances) -> ReconcileReport {
+    let ours: BTreeMap<i64, &KlineRow> = ours.iter().map(|r| (r.timestamp, r)).collect();
+    let reference: BTreeMap<i64, &KlineRow> = reference.iter().map(|r| (r.timestamp, r)).collect();
+    let mut report = ReconcileReport::default();
+    for (&ts, r) in &reference {
+        let Some(o) = ours.get(&ts) else {
+            if r.volume == 0.0 {
+                report.skipped_empty += 1;
+            } else {
+                report.missing += 1;
+                report.differences.push(Difference { timestamp: ts, kind: "missing", field: "", ours: None, reference: None, diff: None });
+            }
+            continue;
+        };
+        report.matched += 1;
+        compare_field(&mut report, ts, "open", o.open, r.open, tol.price);
+        compare_field(&mut report, ts, "high", o.high, r.high, tol.price);
+        compare_field(&mut report, ts, "low", o.low, r.low, tol.price);
+        compare_field(&mut report, ts, "close", o.close, r.close, tol.price);
+        compare_field(&mut report, ts, "volume", o.volume, r.volume, tol.volume);
+        if let (Some(oc), Some(rc)) = (o.trade_count, r.trade_count) {
+            compare_field(&mut report, ts, "trade_count", oc as f64, rc as f64, tol.trade_count);
+        }
+    }
+    for &ts in ours.keys() {
+        if !reference.contains_key(&ts) {
+            report.extra += 1;
+            report.differences.push(Difference { timestamp: ts, kind: "extra", field: "", ours: None, reference: None, diff: None });
+        }
+    }
+    report.differences.sort_by_key(|d| d.timestamp);
+    report
+}
+
+fn print_report(report: &ReconcileReport) {
+    println!("\n=== Reconcile Summary ===");
+    println!("Matched candles: {}", report.matched);
+    println!("Missing candles (in reference only): {}", report.missing);
+    println!("Extra candles (in output only): {}", report.extra);
+    println!("Skipped empty reference candles: {}", report.skipped_empty);
+    println!("Per-field differences:");
+    for (field, s) in &report.fields {
+        let mean = if s.compared > 0 { s.sum_abs_diff / s.compared as f64 } else { 0.0 };
+        println!("  {:<12} mismatches: {:<8} max |diff|: {:<14} mean |diff|: {}", field, s.mismatches, s.max_abs_diff, mean);
+    }
+}
+
+/// Режим сверки: `--input` указывает на наши свечи (CSV-файл или директорию),
+/// `--reconcile` — на эталонные klines. Возвращает false, если расхождения превышают допуски.
+pub fn run_reconcile(args: &Args, reference_path: &Path) -> Result<bool> {
+    let intervals = parse_intervals(&args.interval);
+    let [tf] = intervals.as_slice() else {
+        anyhow::bail!("Reconcile mode needs exactly one interval, got {:?}", args.interval);
+    };
+    let ours = load_klines_path(&args.input)?;
+    let reference = load_klines_path(reference_path)?;
+    println!("Reconcile {} {:?}: {} candles vs {} reference candles", args.symbol()?, tf, ours.len(), reference.len());
+    let step = timeframe_millis(tf);
+    let misaligned = reference.iter().filter(|r| r.timestamp.rem_euclid(step) != 0).count();
+    if misaligned > 0 {
+        println!("Warning: {} reference candles are not aligned to {:?}", misaligned, tf);
+    }
+    let tol = Tolerances { price: args.price_tolerance, volume: args.volume_tolerance, trade_count: args.trade_count_tolerance };
+    let report = reconcile(&ours, &reference, tol);
+    print_report(&report);
+    let out_dir = args.output.clone().unwrap_or_else(|| PathBuf::from("candles"));
+    fs::create_dir_all(&out_dir)?;
+    let report_path = out_dir.join(format!("reconcile_{}_{:?}.csv", args.symbol()?, tf));
+    let mut wtr = WriterBuilder::new().has_headers(true).from_path(&report_path)?;
+    for d in &report.differences {
+        wtr.serialize(d)?;
+    }
+    wtr.flush()?;
+    println!("Differences: {} -> {:?}", report.differences.len(), report_path);
+    Ok(report.is_clean())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn row(ts: i64, close: f64, volume: f64) -> KlineRow {
+        KlineRow { timestamp: ts, open: close, high: close, low: close, close, volume, trade_count: None }
+    }
+
+    #[test]
+    fn test_load_klines_binance() {
+        let data = "1714000020000,50000.0,50100.0,49900.0,50050.0,1.5,1714000079999,75000.0,12,0.7,35000.0,0\n\
+                    1714000080000000,50050.0,50060.0,50040.0,50055.0,0.5,1714000139999999,25000.0,3,0.2,10000.0,0\n";
+        let rows = load_klines(Cursor::new(data)).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].high, 50100.0);
+        assert_eq!(rows[0].trade_count, Some(12));
+        assert_eq!(rows[1].timestamp, 1714000080000);
+    }
+
+    #[test]
+    fn test_load_klines_malformed_row() {
+        let data = "timestamp,open,high,low,close,volume\n\
+                    1714000000000,1,1,1,1,1\n\
+                    17140x0060000,1,1,1,1,1\n";
+        let err = load_klines(Cursor::new(data)).unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_klines_own_output() {
+        let data = "timestamp,open,high,low,close,volume\n1714000020000,50000.0,50100.0,49900.0,50050.0,1.5\n";
+        let rows = load_klines(Cursor::new(data)).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].volume, 1.5);
+        assert_eq!(rows[0].trade_count, None);
+        let data = "timestamp,open,high,low,close,volume,trade_count\n1714000020000,50000.0,50100.0,49900.0,50050.0,1.5,12\n";
+        assert_eq!(load_klines(Cursor::new(data)).unwrap()[0].trade_count, Some(12));
+    }
+
+    #[test]
+    fn test_reconcile_clean() {
+        let ours = vec![row(1714000020000, 50000.0, 1.0), row(1714000080000, 50100.0, 2.0)];
+        let reference = vec![row(1714000020000, 50000.0, 1.0), row(1714000080000, 50100.0, 2.0), row(1714000140000, 50100.0, 0.0)];
+        let report = reconcile(&ours, &reference, Tolerances { price: 0.0, volume: 1e-9, trade_count: 0.0 });
+        assert!(report.is_clean());
+        assert_eq!(report.matched, 2);
+        assert_eq!(report.skipped_empty, 1);
+    }
+
+    #[test]
+    fn test_reconcile_differences() {
+        let ours = vec![row(1714000020000, 50000.0, 1.0), row(1714000200000, 50200.0, 1.0)];
+        let reference = vec![row(1714000020000, 50000.5, 1.0), row(1714000080000, 50100.0, 2.0)];
+        let strict = reconcile(&ours, &reference, Tolerances { price: 0.0, volume: 0.0, trade_count: 0.0 });
+        assert!(!strict.is_clean());
+        assert_eq!(strict.missing, 1);
+        assert_eq!(strict.extra, 1);
+        assert_eq!(strict.fields["close"].mismatches, 1);
+        let loose = reconcile(&ours, &reference, Tolerances { price: 1e-4, volume: 0.0, trade_count: 0.0 });
+        assert_eq!(loose.fields["close"].mismatches, 0);
+    }
+
+    #[test]
+    fn test_reconcile_trade_count() {
+        let with_count = |ts, count| KlineRow { trade_count: Some(count), ..row(ts, 50000.0, 1.0) };
+        let ours = vec![with_count(1714000020000, 100), with_count(1714000080000, 12), row(1714000140000, 50000.0, 1.0)];
+        let reference = vec![with_count(1714000020000, 100), with_count(1714000080000, 13), with_count(1714000140000, 7)];
+        let strict = reconcile(&ours, &reference, Tolerances { price: 0.0, volume: 0.0, trade_count: 0.0 });
+        // Без числа сделок у одной из сторон колонка не сравнивается
+        assert_eq!(strict.fields["trade_count"].compared, 2);
+        assert_eq!(strict.fields["trade_count"].mismatches, 1);
+        assert_eq!(strict.differences[0].field, "trade_count");
+        let loose = reconcile(&ours, &reference, Tolerances { price: 0.0, volume: 0.0, trade_count: 0.1 });
+        assert!(loose.is_clean());
+    }
+}
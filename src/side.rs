@@ -0,0 +1,85 @@
+use candle_generator::{Side, Trade};
+
+/// Определяет сторону агрессора по tick rule для трейдов с `Side::Unknown`:
+/// рост цены относительно предыдущего трейда — покупка, падение — продажа,
+/// при неизменной цене берётся направление последнего ненулевого изменения.
+/// Трейды с известной стороной не меняются, но участвуют в расчёте изменений.
+/// Трейды должны идти в хронологическом порядке (пайплайн сортирует их перед вызовом).
+/// Возвращает количество трейдов, для которых сторона была выведена.
+pub fn infer_sides(trades: &mut [Trade]) -> usize {
+    let mut inferred = 0;
+    let mut prev_price: Option<f64> = None;
+    let mut last_direction: Option<Side> = None;
+    for trade in trades.iter_mut() {
+        if let Some(prev) = prev_price {
+            if trade.price > prev {
+                last_direction = Some(Side::Buy);
+            } else if trade.price < prev {
+                last_direction = Some(Side::Sell);
+            }
+        }
+        prev_price = Some(trade.price);
+        if matches!(trade.side, Side::Unknown) {
+            match last_direction {
+                Some(Side::Buy) => trade.side = Side::Buy,
+                Some(Side::Sell) => trade.side = Side::Sell,
+                _ => continue,
+            }
+            inferred += 1;
+        }
+    }
+    inferred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_generator::{Instrument, Pair, MarketType};
+    use chrono::{TimeZone, Utc};
+
+    fn sample_trade(ts: i64, price: f64, side: Side) -> Trade {
+        Trade {
+            instrument: Instrument {
+                pair: Pair { base_id: "BTC".to_string(), quote_id: "USDT".to_string() },
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+            },
+            id: format!("{}", ts),
+            price,
+            amount: 0.1,
+            side,
+            timestamp: Utc.timestamp_millis_opt(ts).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_infer_sides_tick_rule() {
+        let mut trades = vec![
+            sample_trade(1714000000000, 50000.0, Side::Unknown),
+            sample_trade(1714000001000, 50010.0, Side::Unknown),
+            sample_trade(1714000002000, 50010.0, Side::Unknown),
+            sample_trade(1714000003000, 50005.0, Side::Unknown),
+            sample_trade(1714000004000, 50005.0, Side::Unknown),
+        ];
+        let inferred = infer_sides(&mut trades);
+        assert_eq!(inferred, 4);
+        assert_eq!(trades[0].side, Side::Unknown);
+        assert_eq!(trades[1].side, Side::Buy);
+        assert_eq!(trades[2].side, Side::Buy);
+        assert_eq!(trades[3].side, Side::Sell);
+        assert_eq!(trades[4].side, Side::Sell);
+    }
+
+    #[test]
+    fn test_infer_sides_keeps_known() {
+        let mut trades = vec![
+            sample_trade(1714000000000, 50000.0, Side::Sell),
+            sample_trade(1714000001000, 50010.0, Side::Sell),
+            sample_trade(1714000002000, 50010.0, Side::Unknown),
+        ];
+        let inferred = infer_sides(&mut trades);
+        assert_eq!(inferred, 1);
+        assert_eq!(trades[1].side, Side::Sell);
+        assert_eq!(trades[2].side, Side::Buy);
+    }
+}
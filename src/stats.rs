@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
+use crate::progress;
 
 #[derive(Debug, Default)]
 pub struct ProcessingStats {
@@ -10,6 +11,11 @@ pub struct ProcessingStats {
     pub trade_processing_time: Duration,
     pub aggregation_time: Duration,
     pub io_time: Duration,
+    pub validation_violations: usize,
+    pub verify_mismatches: usize,
+    pub rejected_trades: HashMap<String, usize>, // reason -> count
+    pub inferred_sides: usize,
+    pub malformed_lines: usize,
     start_time: Option<Instant>,
 }
 
@@ -34,26 +40,62 @@ impl ProcessingStats {
     pub fn add_candles(&mut self, tf: &str, n: usize) {
         *self.total_candles.entry(tf.to_string()).or_default() += n;
     }
+    pub fn add_rejected(&mut self, reason: &str) {
+        *self.rejected_trades.entry(reason.to_string()).or_default() += 1;
+    }
+    pub fn add_inferred_sides(&mut self, n: usize) {
+        self.inferred_sides += n;
+    }
+    pub fn add_malformed_lines(&mut self, n: usize) {
+        self.malformed_lines += n;
+    }
+    pub fn add_verify_mismatches(&mut self, n: usize) {
+        self.verify_mismatches += n;
+    }
+    pub fn add_violations(&mut self, n: usize) {
+        self.validation_violations += n;
+    }
 }
 
 pub fn print_summary(stats: &ProcessingStats) {
-    println!("\n=== Processing Summary ===");
-    println!("Total files processed: {}", stats.total_files);
-    println!("Total trades processed: {}", stats.total_trades);
-    println!("Candles generated by timeframe:");
+    progress!("\n=== Processing Summary ===");
+    progress!("Total files processed: {}", stats.total_files);
+    progress!("Total trades processed: {}", stats.total_trades);
+    if !stats.rejected_trades.is_empty() {
+        let total: usize = stats.rejected_trades.values().sum();
+        progress!("Trades rejected by filter: {}", total);
+        let mut reasons: Vec<_> = stats.rejected_trades.iter().collect();
+        reasons.sort();
+        for (reason, count) in reasons {
+            progress!("  {:<20} {}", reason, count);
+        }
+    }
+    if stats.malformed_lines > 0 {
+        progress!("Malformed input lines skipped: {}", stats.malformed_lines);
+    }
+    if stats.inferred_sides > 0 {
+        progress!("Trades with inferred side (tick rule): {}", stats.inferred_sides);
+    }
+    progress!("Candles generated by timeframe:");
     let mut tfs: Vec<_> = stats.total_candles.iter().collect();
     tfs.sort_by_key(|&(tf, _)| tf.clone());
     for (tf, count) in tfs {
-        println!("  {:<8} candles: {}", tf, count);
+        progress!("  {:<8} candles: {}", tf, count);
+    }
+    if stats.validation_violations > 0 {
+        progress!("Validation violations: {}", stats.validation_violations);
+    }
+    if stats.verify_mismatches > 0 {
+        progress!("Verify mismatches (chained vs direct): {}", stats.verify_mismatches);
     }
-    println!("\nTiming breakdown:");
-    println!("  Total processing time: {:.2?}", stats.processing_time);
-    println!("  Trade processing time: {:.2?}", stats.trade_processing_time);
-    println!("  Aggregation time: {:.2?}", stats.aggregation_time);
-    println!("  I/O time: {:.2?}", stats.io_time);
+    progress!("\nTiming breakdown:");
+    progress!("  Total processing time: {:.2?}", stats.processing_time);
+    progress!("  Trade processing time: {:.2?}", stats.trade_processing_time);
+    progress!("  Aggregation time: {:.2?}", stats.aggregation_time);
+    progress!("  I/O time: {:.2?}", stats.io_time);
     if stats.trade_processing_time.as_secs_f64() > 0.0 {
         let tps = stats.total_trades as f64 / stats.trade_processing_time.as_secs_f64();
-        println!("\nPerformance metrics:");
-        println!("  Trades processed per second: {:.0}", tps);
+        progress!("\nPerformance metrics:");
+        progress!("  Trades processed per second: {:.0}", tps);
     }
 } 
\ No newline at end of file
@@ -0,0 +1,199 @@
+use anyhow::Result;
+use candle_generator::{Candle, Timeframe};
+use csv::WriterBuilder;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use crate::aggregation::timeframe_millis;
+
+/// Нарушение инварианта свечи, попадает в отчёт validation_report.csv
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub symbol: String,
+    pub source: String,
+    pub timeframe: String,
+    pub timestamp: i64,
+    pub rule: &'static str,
+    pub detail: String,
+}
+
+fn approx_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+}
+
+fn check_candles(candles: &[Candle], tf: &Timeframe, mut report: impl FnMut(i64, &'static str, String)) {
+    let step = timeframe_millis(tf);
+    let mut prev_ts: Option<i64> = None;
+    for c in candles {
+        let ts = c.timestamp.timestamp_millis();
+        let in_range = c.low <= c.open && c.low <= c.close && c.open <= c.high && c.close <= c.high;
+        if !in_range {
+            report(ts, "ohlc_range", format!("open={} high={} low={} close={}", c.open, c.high, c.low, c.close));
+        }
+        if c.volume < 0.0 || c.volume.is_nan() {
+            report(ts, "negative_volume", format!("volume={}", c.volume));
+        }
+        if let Some(v) = c.volume_usdt {
+            if v < 0.0 || v.is_nan() {
+                report(ts, "negative_volume_usdt", format!("volume_usdt={}", v));
+            }
+        }
+        if c.trade_count == 0 && c.volume > 0.0 {
+            report(ts, "zero_trade_count", format!("volume={} with trade_count=0", c.volume));
+        }
+        if ts.rem_euclid(step) != 0 {
+            report(ts, "misaligned_timestamp", format!("offset={}ms", ts.rem_euclid(step)));
+        }
+        if let Some(prev) = prev_ts {
+            if ts <= prev {
+                report(ts, "non_increasing_timestamp", format!("previous={}", prev));
+            }
+        }
+        prev_ts = Some(ts);
+    }
+}
+
+fn check_rollup(higher: &[Candle], tf: &Timeframe, lower: &[Candle], mut report: impl FnMut(i64, &'static str, String)) {
+    let step = timeframe_millis(tf);
+    // Обе последовательности отсортированы по времени (порядок проверяет check_candles), поэтому
+    // дочерние свечи собираются одним проходом курсора по младшему таймфрейму
+    let mut cursor = 0;
+    for c in higher {
+        let start = c.timestamp.timestamp_millis();
+        while cursor < lower.len() && lower[cursor].timestamp.timestamp_millis() < start {
+            cursor += 1;
+        }
+        let mut children_volume = 0.0;
+        while cursor < lower.len() && lower[cursor].timestamp.timestamp_millis() < start + step {
+            children_volume += lower[cursor].volume;
+            cursor += 1;
+        }
+        if !approx_eq(c.volume, children_volume) {
+            report(start, "rollup_volume", format!("volume={} children_sum={}", c.volume, children_volume));
+        }
+    }
+}
+
+/// Проверяет свечи всех таймфреймов цепочки. `timeframes` — в порядке агрегации,
+/// каждый следующий таймфрейм сверяется по объёму с предыдущим.
+pub fn validate_chain(symbol: &str, source: &str, chain: &HashMap<Timeframe, Vec<Candle>>, timeframes: &[Timeframe]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for (i, tf) in timeframes.iter().enumerate() {
+        let Some(candles) = chain.get(tf) else { continue };
+        let mut report = |timestamp: i64, rule: &'static str, detail: String| {
+            violations.push(Violation {
+                symbol: symbol.to_string(),
+                source: source.to_string(),
+                timeframe: format!("{:?}", tf),
+                timestamp,
+                rule,
+                detail,
+            });
+        };
+        check_candles(candles, tf, &mut report);
+        if i > 0 {
+            if let Some(lower) = chain.get(&timeframes[i - 1]) {
+                check_rollup(candles, tf, lower, &mut report);
+            }
+        }
+    }
+    violations
+}
+
+pub fn write_report<P: AsRef<Path>>(violations: &[Violation], out_path: P) -> Result<()> {
+    let mut wtr = WriterBuilder::new().has_headers(true).from_path(out_path)?;
+    for v in violations {
+        wtr.serialize(v)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_generator::{Instrument, Pair, MarketType};
+    use chrono::{TimeZone, Utc};
+
+    fn sample_candle(tf: Timeframe, ts: i64, open: f64, volume: f64) -> Candle {
+        Candle {
+            instrument: Instrument {
+                pair: Pair { base_id: "BTC".to_string(), quote_id: "USDT".to_string() },
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+            },
+            interval: tf,
+            timestamp: Utc.timestamp_millis_opt(ts).unwrap(),
+            open,
+            high: open,
+            low: open,
+            close: open,
+            volume,
+            trade_count: 1,
+            volume_usdt: Some(open * volume),
+            custom: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate_chain_ok() {
+        let mut chain = HashMap::new();
+        chain.insert(Timeframe::m1, vec![
+            sample_candle(Timeframe::m1, 1713999900000, 50000.0, 1.0),
+            sample_candle(Timeframe::m1, 1713999960000, 50100.0, 2.0),
+        ]);
+        chain.insert(Timeframe::m5, vec![sample_candle(Timeframe::m5, 1713999900000, 50000.0, 3.0)]);
+        let violations = validate_chain("BTCUSDT", "test", &chain, &[Timeframe::m1, Timeframe::m5]);
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+
+    #[test]
+    fn test_validate_chain_ohlc_and_volume() {
+        let mut bad = sample_candle(Timeframe::m1, 1714000020000, 50000.0, -1.0);
+        bad.low = 50100.0;
+        let mut chain = HashMap::new();
+        chain.insert(Timeframe::m1, vec![bad]);
+        let violations = validate_chain("BTCUSDT", "test", &chain, &[Timeframe::m1]);
+        let rules: Vec<_> = violations.iter().map(|v| v.rule).collect();
+        assert!(rules.contains(&"ohlc_range"));
+        assert!(rules.contains(&"negative_volume"));
+    }
+
+    #[test]
+    fn test_validate_chain_zero_trade_count() {
+        let mut empty = sample_candle(Timeframe::m1, 1714000020000, 50000.0, 1.0);
+        empty.trade_count = 0;
+        let mut chain = HashMap::new();
+        chain.insert(Timeframe::m1, vec![empty]);
+        let violations = validate_chain("BTCUSDT", "test", &chain, &[Timeframe::m1]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].rule, "zero_trade_count");
+    }
+
+    #[test]
+    fn test_validate_chain_misaligned_rollup() {
+        let mut chain = HashMap::new();
+        chain.insert(Timeframe::m1, vec![
+            sample_candle(Timeframe::m1, 1713999960000, 50000.0, 1.0),
+            sample_candle(Timeframe::m1, 1714000020000, 50100.0, 2.0),
+        ]);
+        // m5-свеча начинается не на границе таймфрейма и не совпадает по объёму с m1
+        chain.insert(Timeframe::m5, vec![sample_candle(Timeframe::m5, 1713999960000, 50000.0, 5.0)]);
+        let violations = validate_chain("BTCUSDT", "test", &chain, &[Timeframe::m1, Timeframe::m5]);
+        let rules: Vec<_> = violations.iter().map(|v| v.rule).collect();
+        assert!(rules.contains(&"misaligned_timestamp"));
+        assert!(rules.contains(&"rollup_volume"));
+    }
+
+    #[test]
+    fn test_validate_chain_non_increasing() {
+        let mut chain = HashMap::new();
+        chain.insert(Timeframe::m1, vec![
+            sample_candle(Timeframe::m1, 1714000020000, 50000.0, 1.0),
+            sample_candle(Timeframe::m1, 1714000020000, 50100.0, 2.0),
+        ]);
+        let violations = validate_chain("BTCUSDT", "test", &chain, &[Timeframe::m1]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].rule, "non_increasing_timestamp");
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use candle_generator::{Candle, CandleGenerator, Timeframe, Trade};
+use csv::WriterBuilder;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use crate::aggregation::{apply_trade_extras, TradeExtra};
+
+/// Расхождение между свечой из цепочки и свечой, построенной напрямую из трейдов
+#[derive(Debug, Clone, Serialize)]
+pub struct Mismatch {
+    pub symbol: String,
+    pub source: String,
+    pub timeframe: String,
+    pub timestamp: i64,
+    pub field: &'static str,
+    pub chained: Option<f64>,
+    pub direct: Option<f64>,
+}
+
+fn same(field: &str, a: f64, b: f64) -> bool {
+    match field {
+        // Суммы зависят от порядка сложения
+        "volume" => (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0),
+        _ => a == b,
+    }
+}
+
+fn candle_fields(c: &Candle) -> [(&'static str, f64); 6] {
+    [
+        ("open", c.open),
+        ("high", c.high),
+        ("low", c.low),
+        ("close", c.close),
+        ("volume", c.volume),
+        ("trade_count", c.trade_count as f64),
+    ]
+}
+
+/// Пересчитывает каждый старший таймфрейм цепочки напрямую через `CandleGenerator::aggregate`
+/// (с теми же `extras`, что и цепочка) и сравнивает OHLCV и trade_count по временным меткам.
+pub fn verify_chain<'a>(
+    trades: impl Iterator<Item = &'a Trade> + Clone,
+    extras: Option<&[TradeExtra]>,
+    chain: &HashMap<Timeframe, Vec<Candle>>,
+    timeframes: &[Timeframe],
+    symbol: &str,
+    source: &str,
+) -> Vec<Mismatch> {
+    let generator = CandleGenerator::default();
+    let mut mismatches = Vec::new();
+    for tf in timeframes.iter().skip(1) {
+        let Some(chained) = chain.get(tf) else { continue };
+        let mut direct = generator.aggregate(trades.clone(), tf.clone());
+        if let Some(extras) = extras {
+            apply_trade_extras(&mut direct, trades.clone(), extras, tf);
+        }
+        let chained: BTreeMap<i64, &Candle> = chained.iter().map(|c| (c.timestamp.timestamp_millis(), c)).collect();
+        let direct: BTreeMap<i64, &Candle> = direct.iter().map(|c| (c.timestamp.timestamp_millis(), c)).collect();
+        let mut push = |timestamp: i64, field: &'static str, chained: Option<f64>, direct: Option<f64>| {
+            mismatches.push(Mismatch {
+                symbol: symbol.to_string(),
+                source: source.to_string(),
+                timeframe: format!("{:?}", tf),
+                timestamp,
+                field,
+                chained,
+                direct,
+            });
+        };
+        for (&ts, c) in &chained {
+            match direct.get(&ts) {
+                Some(d) => {
+                    for ((field, cv), (_, dv)) in candle_fields(c).into_iter().zip(candle_fields(d)) {
+                        if !same(field, cv, dv) {
+                            push(ts, field, Some(cv), Some(dv));
+                        }
+                    }
+                }
+                None => push(ts, "missing_in_direct", None, None),
+            }
+        }
+        for &ts in direct.keys() {
+            if !chained.contains_key(&ts) {
+                push(ts, "missing_in_chain", None, None);
+            }
+        }
+    }
+    mismatches
+}
+
+pub fn write_report<P: AsRef<Path>>(mismatches: &[Mismatch], out_path: P) -> Result<()> {
+    let mut wtr = WriterBuilder::new().has_headers(true).from_path(out_path)?;
+    for m in mismatches {
+        wtr.serialize(m)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregation::aggregate_trades_chain;
+    use candle_generator::{Instrument, Pair, MarketType, Side};
+    use chrono::{TimeZone, Utc};
+
+    fn sample_trade(ts: i64, price: f64, amount: f64) -> Trade {
+        Trade {
+            instrument: Instrument {
+                pair: Pair { base_id: "BTC".to_string(), quote_id: "USDT".to_string() },
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+            },
+            id: format!("{}", ts),
+            price,
+            amount,
+            side: Side::Buy,
+            timestamp: Utc.timestamp_millis_opt(ts).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_verify_chain_agrees() {
+        // Пять полных минут, начиная с границы m5
+        let trades: Vec<Trade> = (0..5).map(|i| sample_trade(1713999900000 + i * 60_000, 50000.0 + i as f64, 0.1)).collect();
+        let tfs = vec![Timeframe::m1, Timeframe::m5];
+        let chain = aggregate_trades_chain(trades.iter(), &tfs);
+        let mismatches = verify_chain(trades.iter(), None, &chain, &tfs, "BTCUSDT", "test");
+        assert!(mismatches.is_empty(), "{:?}", mismatches);
+    }
+
+    #[test]
+    fn test_verify_chain_reports_misaligned_rollup() {
+        // Первая минута не на границе m5: цепочка по счётчику склеит не те свечи
+        let trades: Vec<Trade> = (0..6).map(|i| sample_trade(1713999960000 + i * 60_000, 50000.0 + i as f64, 0.1)).collect();
+        let tfs = vec![Timeframe::m1, Timeframe::m5];
+        let chain = aggregate_trades_chain(trades.iter(), &tfs);
+        let mismatches = verify_chain(trades.iter(), None, &chain, &tfs, "BTCUSDT", "test");
+        assert!(!mismatches.is_empty());
+        assert!(mismatches.iter().all(|m| m.timeframe == "m5"));
+    }
+}
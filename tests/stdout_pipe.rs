@@ -0,0 +1,50 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const BIN: &str = env!("CARGO_BIN_EXE_candle_batch_aggregator");
+
+/// При `--output -` в stdout идут только свечи: прогресс пакетного режима уходит в stderr
+#[test]
+fn test_output_dash_keeps_stdout_clean_for_directory_input() {
+    let input = std::env::temp_dir().join(format!("candle_batch_aggregator_stdout_pipe_{}", std::process::id()));
+    fs::create_dir_all(input.join("BTCUSDT")).unwrap();
+    fs::write(
+        input.join("BTCUSDT/trades.csv"),
+        "timestamp,price,amount,side\n1713999960000,50000.0,0.1,buy\n1713999990000,50010.0,0.2,sell\n",
+    )
+    .unwrap();
+    let output = Command::new(BIN)
+        .args(["-i", input.to_str().unwrap(), "-s", "ALL", "-f", "csv", "-o", "-"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&input).ok();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "unexpected stdout:\n{}", stdout);
+    assert!(lines[0].starts_with("timestamp,"));
+    assert!(lines[1].starts_with("1713999960000,50000.0,50010.0"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Batch symbols"));
+    assert!(stderr.contains("File: \"trades.csv\""));
+}
+
+/// `--symbol` обязателен для директории и необязателен для stdin
+#[test]
+fn test_symbol_required_unless_stdin() {
+    let output = Command::new(BIN).args(["-i", "."]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--symbol"));
+    let mut child = Command::new(BIN)
+        .args(["-i", "-", "-o", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"timestamp,price,amount\n1714000000000,1.0,1.0\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 2);
+}